@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, fs::File, io::{self, BufReader, BufWriter, Write}, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{counting::FileLocation, options::DuplicateUnit};
+
+/// Bumped whenever the file format or the way fingerprints are computed
+/// changes
+pub const BASELINE_VERSION: u32 = 1;
+
+/// The duplicate groups that were known about at some point, so that only
+/// new duplication gets reported
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Baseline {
+    pub version: u32,
+    /// Number of occurrences of each group, by fingerprint
+    pub groups: BTreeMap<String, usize>,
+}
+
+impl Baseline {
+    pub fn from_duplicates(duplicates: &[(String, &Vec<FileLocation>)]) -> Self {
+        let mut groups = BTreeMap::new();
+
+        for (fingerprint, locations) in duplicates {
+            *groups.entry(fingerprint.clone()).or_insert(0) += locations.len();
+        }
+
+        Baseline {
+            version: BASELINE_VERSION,
+            groups,
+        }
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let baseline: Baseline = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+
+        if baseline.version != BASELINE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported baseline version {} (expected {})", baseline.version, BASELINE_VERSION),
+            ));
+        }
+
+        return Ok(baseline);
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+
+        serde_json::to_writer_pretty(&mut out, self)?;
+        writeln!(out)?;
+
+        return out.flush();
+    }
+
+    /// Whether a group is new, or has more occurrences than when the baseline
+    /// was recorded
+    pub fn is_new(&self, fingerprint: &str, occurrences: usize) -> bool {
+        self.groups.get(fingerprint)
+            .map(|&known| occurrences > known)
+            .unwrap_or(true)
+    }
+}
+
+/// A stable identifier for a duplicate group, based only on what it's made
+/// of (`unit`), its normalized text and, when duplicates are only looked for
+/// within single files, the file (relative to the directory searched). Line
+/// numbers aren't included, so unrelated edits don't change it.
+pub fn fingerprint(unit: DuplicateUnit, text: &str, file: Option<&Path>) -> String {
+    let mut hash = Fnv1a::new();
+
+    hash.write(unit.name().as_bytes());
+    hash.write(&[0]);
+    if let Some(file) = file {
+        hash.write(file.to_string_lossy().as_bytes());
+    }
+    hash.write(&[0]);
+    hash.write(text.as_bytes());
+
+    return format!("{:016x}", hash.0);
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher`, its output is guaranteed not to
+/// change between Rust versions, which baselines depend on.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn fnv1a_matches_the_reference() {
+        let mut hash = Fnv1a::new();
+        hash.write(b"a");
+
+        assert_eq!(hash.0, 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn fingerprints_never_change() {
+        assert_eq!(fingerprint(DuplicateUnit::Lines, "let x = 1;", None), "61636df55e0dd0e2");
+        assert_eq!(fingerprint(DuplicateUnit::Blocks, "a\nb", Some(Path::new("src/a.rs"))), "562d096c65b758c1");
+    }
+
+    #[test]
+    fn groups_are_new_until_recorded_and_again_when_they_grow() {
+        let mut baseline = Baseline::default();
+        baseline.groups.insert(String::from("known"), 2);
+
+        assert!(baseline.is_new("unknown", 2));
+        assert!(!baseline.is_new("known", 1));
+        assert!(!baseline.is_new("known", 2));
+        assert!(baseline.is_new("known", 3));
+    }
+
+    #[test]
+    fn baselines_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("baseline.json");
+        let locations = (1..=2)
+            .map(|line_number| FileLocation {
+                path:            PathBuf::from("a.txt"),
+                line_number,
+                column:          1,
+                end_line_number: line_number,
+                raw_text:        None,
+            })
+            .collect::<Vec<FileLocation>>();
+
+        Baseline::from_duplicates(&[(String::from("f"), &locations)]).write(&path).unwrap();
+        let baseline = Baseline::read(&path).unwrap();
+
+        assert_eq!(baseline.version, BASELINE_VERSION);
+        assert_eq!(baseline.groups.get("f"), Some(&2));
+    }
+
+    #[test]
+    fn baselines_with_another_version_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("baseline.json");
+        std::fs::write(&path, format!("{{\"version\": {}, \"groups\": {{}}}}", BASELINE_VERSION + 1)).unwrap();
+
+        let error = Baseline::read(&path).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("unsupported baseline version"));
+    }
+}
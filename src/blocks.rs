@@ -1,60 +1,75 @@
-use std::{collections::{HashMap, HashSet}, hash::BuildHasherDefault, path::Path};
-
-use rustc_hash::FxHasher;
-
-use crate::{counting::{Occurrences, record_line}, options::Options};
-
-type OccurrencesMap<TSequence, TLocation> = HashMap<TSequence, HashSet<TLocation, BuildHasherDefault<FxHasher>>, BuildHasherDefault<FxHasher>>;
-
-pub fn count_chunks(
-    file_path: &Path,
-    text: &str,
-    options: &Options,
-) -> Occurrences {
-    let mut records = HashMap::new();
-    
-    let lines = text.lines()
-        .map(|line| if options.trim_whitespace { line.trim() } else { line })
-        .filter(|line| line.len() > 0)
-        .collect::<Vec<&str>>();
-
-    let chunks = all_chunks(&lines);
-
-    for (block, line_numbers) in chunks {
-        let block_string = block.join("\n");
-        
-        for line_number in line_numbers {
-            record_line(
-                &options,
-                &mut records,
-                file_path,
-                &block_string,
-                line_number,
-            );
-        }
-    }
-
-    return records;
-}
-
-fn all_chunks<'a>(items: &'a [&'a str]) -> OccurrencesMap<&'a [&'a str], usize> {
-    let mut chunk_occurrences: OccurrencesMap<&'a [&'a str], usize> = HashMap::with_hasher(BuildHasherDefault::default());
-    // println!("{:?}", items);
-    for start in 0..items.len()-1 {
-        // println!("start: {}", start);
-        for end in start+2..items.len()+1 {
-            // println!("  end: {}", end);
-            let chunk = &items[start..end];
-            
-            if chunk_occurrences.contains_key(chunk) {
-                chunk_occurrences.get_mut(chunk).unwrap().insert(start);
-            } else {
-                chunk_occurrences.insert(chunk, [start].iter().map(|x| *x).collect());
-            }
-        }
-    }
-
-    // println!("{:?}", &chunk_occurrences);
-
-    return chunk_occurrences;
-}
+use std::path::Path;
+
+use crate::{comments::comparison_text, counting::{CharOrLine, Occurrences, line_is_included, walk_lines}, options::Options, repeats::{Sequence, Unit, count_repeats}, suppress::suppressed_lines};
+
+/// Return a record of every duplicated block of lines within a single file
+pub fn count_chunks(
+    file_path: &Path,
+    text: &str,
+    options: &Options,
+) -> Occurrences {
+    count_blocks(&block_sequences(file_path, text, options), options)
+}
+
+/// Return a record of every duplicated block of lines across all of
+/// `sequences`. Only maximal blocks are recorded: a block is not reported
+/// again for each of its sub-blocks, unless one of those also occurs
+/// somewhere else. Blocks with more than `options.max_block_lines` lines are
+/// left out entirely rather than cut short.
+pub fn count_blocks(sequences: &[Sequence], options: &Options) -> Occurrences {
+    let lengths = options.min_block_lines..=options.max_block_lines.unwrap_or(usize::MAX);
+
+    return count_repeats(sequences, lengths, "\n", false);
+}
+
+/// Turn a file into sequences of its normalized lines, split up the same way
+/// as in `count_lines` so that line numbers match. Lines that normalize to
+/// nothing are left out, so they don't break up blocks; lines that don't pass
+/// the line filters or are suppressed end one sequence and start the next, so
+/// no block can include them.
+pub fn block_sequences(
+    file_path: &Path,
+    text: &str,
+    options: &Options,
+) -> Vec<Sequence> {
+    let mut sequences = Vec::new();
+    let mut units = Vec::new();
+    let mut current_line_number = 0;
+
+    let comparison = comparison_text(file_path, text, options);
+    let suppressed = suppressed_lines(file_path, text, options.line_delimiter);
+
+    walk_lines(&comparison, options,
+        |next| {
+            if let CharOrLine::Line(line) = next {
+                current_line_number += 1;
+
+                let normalized = options.normalizer_for(file_path).normalize(&line);
+
+                if normalized.is_empty() {
+                    // skip
+                } else if !suppressed[current_line_number - 1] && line_is_included(options, &normalized) {
+                    units.push(Unit {
+                        text: normalized,
+                        raw: None,
+                        line_number: current_line_number,
+                        column: 1,
+                    });
+                } else if !units.is_empty() {
+                    sequences.push(Sequence {
+                        path: file_path.to_path_buf(),
+                        units: std::mem::take(&mut units),
+                    });
+                }
+            }
+        });
+
+    if !units.is_empty() {
+        sequences.push(Sequence {
+            path: file_path.to_path_buf(),
+            units,
+        });
+    }
+
+    return sequences;
+}
@@ -0,0 +1,581 @@
+use std::{borrow::Cow, path::Path};
+
+use crate::options::{CommentFilter, Options};
+
+/// How comments and strings are written in a particular language. Strings are
+/// only tracked so that comment markers inside them aren't mistaken for
+/// comments.
+#[derive(Debug)]
+pub struct CommentSyntax {
+    /// Markers that begin a comment running to the end of the line. Longer
+    /// markers must come first (eg. "///" before "//").
+    pub line: &'static [&'static str],
+    /// Start and end markers of block comments, longer starts first
+    pub block: &'static [(&'static str, &'static str)],
+    /// Quotes that begin and end a string literal which can span lines,
+    /// longer quotes first. Backslash escapes are respected inside them.
+    pub multiline_strings: &'static [&'static str],
+    /// Quotes of string literals that can't span lines, longer quotes first.
+    /// One that isn't closed ends with its line, so that a stray apostrophe
+    /// (as in YAML like `title: It's here`) doesn't turn the rest of the file
+    /// into a string.
+    pub strings: &'static [&'static str],
+    /// Whether `'x'` and `'\x'` are character literals. When this is set a
+    /// lone `'` (such as a Rust lifetime) is treated as ordinary code.
+    pub char_literals: bool,
+}
+
+impl CommentSyntax {
+    /// If `text` starts with a string literal, its opening quote and whether
+    /// it can span lines
+    pub fn string_start(&self, text: &str) -> Option<(&'static str, bool)> {
+        let find = |quotes: &[&'static str]| quotes.iter().copied().find(|q| text.starts_with(*q));
+
+        find(self.multiline_strings).map(|q| (q, true))
+            .or_else(|| find(self.strings).map(|q| (q, false)))
+    }
+}
+
+const C_LIKE: CommentSyntax = CommentSyntax {
+    line: &["///", "//!", "//"],
+    block: &[("/**", "*/"), ("/*!", "*/"), ("/*", "*/")],
+    multiline_strings: &["\""],
+    strings: &[],
+    char_literals: true,
+};
+
+const JS_LIKE: CommentSyntax = CommentSyntax {
+    line: &["///", "//"],
+    block: &[("/**", "*/"), ("/*", "*/")],
+    multiline_strings: &["`"],
+    strings: &["\"", "'"],
+    char_literals: false,
+};
+
+const CSS: CommentSyntax = CommentSyntax {
+    line: &[],
+    block: &[("/*", "*/")],
+    multiline_strings: &[],
+    strings: &["\"", "'"],
+    char_literals: false,
+};
+
+const SCSS: CommentSyntax = CommentSyntax {
+    line: &["//"],
+    block: &[("/*", "*/")],
+    multiline_strings: &[],
+    strings: &["\"", "'"],
+    char_literals: false,
+};
+
+const PHP: CommentSyntax = CommentSyntax {
+    line: &["//", "#"],
+    block: &[("/**", "*/"), ("/*", "*/")],
+    multiline_strings: &["\"", "'"],
+    strings: &[],
+    char_literals: false,
+};
+
+const PYTHON: CommentSyntax = CommentSyntax {
+    line: &["#"],
+    block: &[],
+    multiline_strings: &["\"\"\"", "'''"],
+    strings: &["\"", "'"],
+    char_literals: false,
+};
+
+const HASH: CommentSyntax = CommentSyntax {
+    line: &["#"],
+    block: &[],
+    multiline_strings: &[],
+    strings: &["\"", "'"],
+    char_literals: false,
+};
+
+const RUBY: CommentSyntax = CommentSyntax {
+    line: &["#"],
+    block: &[("=begin", "=end")],
+    multiline_strings: &["\"", "'"],
+    strings: &[],
+    char_literals: false,
+};
+
+const POWERSHELL: CommentSyntax = CommentSyntax {
+    line: &["#"],
+    block: &[("<#", "#>")],
+    multiline_strings: &["\"", "'"],
+    strings: &[],
+    char_literals: false,
+};
+
+const SQL: CommentSyntax = CommentSyntax {
+    line: &["--"],
+    block: &[("/*", "*/")],
+    multiline_strings: &["'", "\""],
+    strings: &[],
+    char_literals: false,
+};
+
+const LUA: CommentSyntax = CommentSyntax {
+    line: &["--"],
+    block: &[("--[[", "]]")],
+    multiline_strings: &[],
+    strings: &["\"", "'"],
+    char_literals: false,
+};
+
+const HASKELL: CommentSyntax = CommentSyntax {
+    line: &["--"],
+    block: &[("{-", "-}")],
+    multiline_strings: &[],
+    strings: &["\""],
+    char_literals: true,
+};
+
+const MARKUP: CommentSyntax = CommentSyntax {
+    line: &[],
+    block: &[("<!--", "-->")],
+    multiline_strings: &[],
+    strings: &[],
+    char_literals: false,
+};
+
+const LISP: CommentSyntax = CommentSyntax {
+    line: &[";"],
+    block: &[],
+    multiline_strings: &["\""],
+    strings: &[],
+    char_literals: false,
+};
+
+const INI: CommentSyntax = CommentSyntax {
+    line: &[";", "#"],
+    block: &[],
+    multiline_strings: &[],
+    strings: &[],
+    char_literals: false,
+};
+
+const PERCENT: CommentSyntax = CommentSyntax {
+    line: &["%"],
+    block: &[],
+    multiline_strings: &[],
+    strings: &["\""],
+    char_literals: false,
+};
+
+/// Pick the comment syntax for a file based on its extension
+pub fn syntax_for_path(path: &Path) -> Option<&'static CommentSyntax> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+
+    let syntax = match extension.as_str() {
+        "c" | "h" | "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" | "cs" | "java" | "go" | "rs" | "swift"
+            | "kt" | "kts" | "scala" | "dart" | "groovy" | "m" | "mm" | "zig" | "proto" => &C_LIKE,
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "mts" | "cts" | "json5" | "jsonc" => &JS_LIKE,
+        "css" => &CSS,
+        "scss" | "less" => &SCSS,
+        "php" => &PHP,
+        "py" | "pyi" => &PYTHON,
+        "sh" | "bash" | "zsh" | "fish" | "pl" | "pm" | "r" | "yaml" | "yml" | "toml" | "cmake" | "nim"
+            | "jl" | "ex" | "exs" | "coffee" | "tf" | "dockerfile" | "mk" | "conf" => &HASH,
+        "rb" => &RUBY,
+        "ps1" | "psm1" => &POWERSHELL,
+        "sql" => &SQL,
+        "lua" => &LUA,
+        "hs" | "elm" => &HASKELL,
+        "html" | "htm" | "xml" | "xhtml" | "svg" | "vue" | "md" => &MARKUP,
+        "lisp" | "cl" | "el" | "clj" | "cljs" | "scm" | "rkt" | "asm" | "s" => &LISP,
+        "ini" | "cfg" => &INI,
+        "erl" | "hrl" | "tex" | "sty" | "matlab" => &PERCENT,
+        _ => return None,
+    };
+
+    Some(syntax)
+}
+
+/// Apply `options.comments` to the contents of a file, returning the text that
+/// lines should be compared against. Every line delimiter from `text` is
+/// preserved, so lines in the result correspond one-to-one with lines in
+/// `text`.
+pub fn comparison_text<'a>(file_path: &Path, text: &'a str, options: &Options) -> Cow<'a, str> {
+    match (options.comments, syntax_for_path(file_path)) {
+        (CommentFilter::Keep, _) => Cow::Borrowed(text),
+        (CommentFilter::Strip, None) => Cow::Borrowed(text),
+        (filter, Some(syntax)) => Cow::Owned(filter_comments(text, syntax, filter, options.line_delimiter)),
+
+        // We can't tell what the comments are in a file of an unknown
+        // language, so none of its lines are compared
+        (CommentFilter::Only, None) => text.chars()
+            .filter(|&c| c == options.line_delimiter)
+            .collect(),
+    }
+}
+
+/// Just the text of the comments in a file, with every line delimiter
+/// preserved, or `None` if its language isn't recognized
+pub fn comment_text(file_path: &Path, text: &str, delimiter: char) -> Option<String> {
+    syntax_for_path(file_path).map(|syntax| filter_comments(text, syntax, CommentFilter::Only, delimiter))
+}
+
+enum State {
+    Code,
+    LineComment,
+    BlockComment(&'static str),
+    /// The quote that ends the string, and whether it can span lines
+    Str(&'static str, bool),
+}
+
+/// What a `Piece` of source text is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PieceKind {
+    /// A character of code outside of comments and strings
+    Code,
+    /// A whole character literal
+    CharLiteral,
+    /// Part of a string literal: a quote, a character, or an escape
+    Str,
+    /// The marker that starts or ends a comment
+    CommentMarker,
+    /// A character inside a comment
+    Comment,
+    /// A line delimiter. Newlines are only this when they're the delimiter;
+    /// otherwise they're part of whatever they're in.
+    LineBreak,
+}
+
+/// A piece of source text, found by `Pieces`
+#[derive(Clone, Copy, Debug)]
+pub struct Piece {
+    pub kind: PieceKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Splits source text into pieces of code, comments and strings, using the
+/// comment syntax of its language. This is the one place comments and
+/// strings are recognized, for both comment filtering and tokenizing.
+pub struct Pieces<'a> {
+    text: &'a str,
+    syntax: &'a CommentSyntax,
+    delimiter: char,
+    index: usize,
+    state: State,
+}
+
+impl<'a> Pieces<'a> {
+    pub fn new(text: &'a str, syntax: &'a CommentSyntax, delimiter: char) -> Self {
+        Pieces {
+            text,
+            syntax,
+            delimiter,
+            index: 0,
+            state: State::Code,
+        }
+    }
+
+    /// Whether the last piece returned left off inside a string literal
+    pub fn in_string(&self) -> bool {
+        matches!(self.state, State::Str(..))
+    }
+
+    /// Continue from `index` instead, skipping the code before it. Only
+    /// characters that can't start a comment or string (like the rest of an
+    /// identifier) should be skipped.
+    pub fn skip_to(&mut self, index: usize) {
+        debug_assert!(matches!(self.state, State::Code));
+
+        self.index = self.index.max(index);
+    }
+}
+
+impl<'a> Iterator for Pieces<'a> {
+    type Item = Piece;
+
+    fn next(&mut self) -> Option<Piece> {
+        let rest = &self.text[self.index..];
+        let c = rest.chars().next()?;
+        let syntax = self.syntax;
+
+        let (len, kind) = if c == '\n' || c == self.delimiter {
+            if let (State::LineComment, '\n') | (State::Str(_, false), '\n') = (&self.state, c) {
+                self.state = State::Code;
+            }
+
+            let kind = match self.state {
+                _ if c == self.delimiter => PieceKind::LineBreak,
+                State::BlockComment(_) => PieceKind::Comment,
+                State::Str(..) => PieceKind::Str,
+                _ => PieceKind::Code,
+            };
+
+            (c.len_utf8(), kind)
+        } else {
+            match self.state {
+                State::Code => {
+                    // Block starts are checked first, since some begin with a
+                    // line marker (eg. Lua's "--[[")
+                    if let Some((start, end)) = syntax.block.iter().find(|(start, _)| rest.starts_with(start)) {
+                        self.state = State::BlockComment(end);
+                        (start.len(), PieceKind::CommentMarker)
+                    } else if let Some(marker) = syntax.line.iter().find(|m| rest.starts_with(*m)) {
+                        self.state = State::LineComment;
+                        (marker.len(), PieceKind::CommentMarker)
+                    } else if let Some((quote, multiline)) = syntax.string_start(rest) {
+                        self.state = State::Str(quote, multiline);
+                        (quote.len(), PieceKind::Str)
+                    } else if let Some(len) = char_literal_len(rest).filter(|_| syntax.char_literals) {
+                        (len, PieceKind::CharLiteral)
+                    } else {
+                        (c.len_utf8(), PieceKind::Code)
+                    }
+                },
+                State::LineComment => (c.len_utf8(), PieceKind::Comment),
+                State::BlockComment(end) => {
+                    if rest.starts_with(end) {
+                        self.state = State::Code;
+                        (end.len(), PieceKind::CommentMarker)
+                    } else {
+                        (c.len_utf8(), PieceKind::Comment)
+                    }
+                },
+                State::Str(quote, _) => {
+                    if c == '\\' {
+                        // An escape can't swallow a line break
+                        let escaped_len = rest[1..].chars().next()
+                            .filter(|&e| e != '\n' && e != self.delimiter)
+                            .map(|e| e.len_utf8())
+                            .unwrap_or(0);
+
+                        (1 + escaped_len, PieceKind::Str)
+                    } else if rest.starts_with(quote) {
+                        self.state = State::Code;
+                        (quote.len(), PieceKind::Str)
+                    } else {
+                        (c.len_utf8(), PieceKind::Str)
+                    }
+                },
+            }
+        };
+
+        let start = self.index;
+        self.index += len;
+
+        Some(Piece {
+            kind,
+            start,
+            end: self.index,
+        })
+    }
+}
+
+/// Either strip comments out of `text` or strip everything but comments
+/// (without their markers). `delimiter` is always kept, and so are newlines
+/// when they're the delimiter; otherwise they're kept or dropped along with
+/// the code or comment they're part of.
+fn filter_comments(text: &str, syntax: &CommentSyntax, filter: CommentFilter, delimiter: char) -> String {
+    let keep_comments = filter == CommentFilter::Only;
+    let mut output = String::with_capacity(text.len());
+
+    // Whether something was dropped from the current line, in which case its
+    // trailing whitespace is left over from the removed part and gets trimmed
+    let mut dropped_on_line = false;
+
+    for piece in Pieces::new(text, syntax, delimiter) {
+        let piece_text = &text[piece.start..piece.end];
+        let is_break = piece.kind == PieceKind::LineBreak || piece_text == "\n";
+
+        if is_break {
+            if dropped_on_line {
+                trim_line_end(&mut output, delimiter);
+            }
+
+            dropped_on_line = false;
+        }
+
+        let keep = match piece.kind {
+            PieceKind::LineBreak => true,
+            PieceKind::CommentMarker => false,
+            PieceKind::Comment => keep_comments,
+            PieceKind::Code | PieceKind::CharLiteral | PieceKind::Str => !keep_comments,
+        };
+
+        if keep {
+            output.push_str(piece_text);
+        } else if !is_break {
+            dropped_on_line = true;
+        }
+    }
+
+    if dropped_on_line {
+        trim_line_end(&mut output, delimiter);
+    }
+
+    return output;
+}
+
+/// Trim trailing whitespace from the line currently being written to `output`
+fn trim_line_end(output: &mut String, delimiter: char) {
+    let trimmed_len = output
+        .trim_end_matches(|c: char| c.is_whitespace() && c != '\n' && c != delimiter)
+        .len();
+
+    output.truncate(trimmed_len);
+}
+
+/// If `text` starts with a character literal like `'x'` or `'\n'`, its length
+fn char_literal_len(text: &str) -> Option<usize> {
+    if !text.starts_with('\'') {
+        return None;
+    }
+
+    let mut chars = text.char_indices().skip(1);
+    let (_, first) = chars.next()?;
+
+    if first == '\\' {
+        chars.next()?;
+    }
+
+    match chars.next()? {
+        (i, '\'') => Some(i + 1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(text: &str, extension: &str) -> String {
+        let syntax = syntax_for_path(Path::new(&format!("file.{}", extension))).unwrap();
+        filter_comments(text, syntax, CommentFilter::Strip, '\n')
+    }
+
+    fn only(text: &str, extension: &str) -> String {
+        let syntax = syntax_for_path(Path::new(&format!("file.{}", extension))).unwrap();
+        filter_comments(text, syntax, CommentFilter::Only, '\n')
+    }
+
+    #[test]
+    fn c_like_comments() {
+        assert_eq!(strip("a(); // call\n/* one */ b();\n", "rs"), "a();\n b();\n");
+        assert_eq!(only("a(); // call\n/// doc\n", "rs"), " call\n doc\n");
+    }
+
+    #[test]
+    fn block_comments_keep_their_lines() {
+        assert_eq!(strip("a /* one\ntwo\nthree */ b\n", "c"), "a\n\n b\n");
+        assert_eq!(only("a /* one\ntwo */ b\n", "c"), " one\ntwo\n");
+    }
+
+    #[test]
+    fn newlines_go_with_their_code_or_comment_under_another_delimiter() {
+        let syntax = syntax_for_path(Path::new("file.rs")).unwrap();
+        let text = "a(); // one. two\nb(); /* three.\nfour */ c.\n";
+
+        assert_eq!(filter_comments(text, syntax, CommentFilter::Only, '.'), " one. two three.\nfour.");
+        assert_eq!(filter_comments(text, syntax, CommentFilter::Strip, '.'), "a();.\nb();. c.\n");
+    }
+
+    #[test]
+    fn unknown_languages_have_no_comment_text() {
+        let options = Options { comments: CommentFilter::Only, line_delimiter: '.', ..Options::default() };
+
+        assert_eq!(comparison_text(Path::new("file.xyz"), "a.\nb.\n", &options), "..");
+    }
+
+    #[test]
+    fn block_comments_do_not_nest() {
+        assert_eq!(strip("/* a /* b */ c */\n", "c"), " c */\n");
+    }
+
+    #[test]
+    fn markers_inside_other_comments() {
+        assert_eq!(strip("a // b /* c\nd */ e\n", "c"), "a\nd */ e\n");
+        assert_eq!(strip("a /* b // c */ d\n", "c"), "a  d\n");
+    }
+
+    #[test]
+    fn markers_inside_strings() {
+        assert_eq!(strip("s = \"// not a comment\"; // comment\n", "rs"), "s = \"// not a comment\";\n");
+        assert_eq!(strip("s = \"a \\\" /* b\"; c\n", "c"), "s = \"a \\\" /* b\"; c\n");
+        assert_eq!(strip("url: 'http://x' # comment\n", "yml"), "url: 'http://x'\n");
+    }
+
+    #[test]
+    fn multiline_strings() {
+        assert_eq!(strip("s = \"a\n# b\" # c\n", "rb"), "s = \"a\n# b\"\n");
+        assert_eq!(strip("\"\"\"a\n# b\"\"\" # c\n", "py"), "\"\"\"a\n# b\"\"\"\n");
+        assert_eq!(strip("`a\n// b` // c\n", "js"), "`a\n// b`\n");
+    }
+
+    #[test]
+    fn char_literals() {
+        assert_eq!(strip("let q = '\"'; // quote\n", "rs"), "let q = '\"';\n");
+        assert_eq!(strip("let q = '\\''; // quote\n", "rs"), "let q = '\\'';\n");
+        // A lifetime isn't the start of a literal
+        assert_eq!(strip("fn f<'a>(x: &'a str) // c\n", "rs"), "fn f<'a>(x: &'a str)\n");
+    }
+
+    #[test]
+    fn pieces_split_code_comments_and_strings() {
+        let syntax = syntax_for_path(Path::new("file.rs")).unwrap();
+        let text = "a'b' \"c\\\"\"// d\n";
+        let pieces = Pieces::new(text, syntax, '\n')
+            .map(|piece| (piece.kind, &text[piece.start..piece.end]))
+            .collect::<Vec<_>>();
+
+        assert_eq!(pieces, [
+            (PieceKind::Code, "a"),
+            (PieceKind::CharLiteral, "'b'"),
+            (PieceKind::Code, " "),
+            (PieceKind::Str, "\""),
+            (PieceKind::Str, "c"),
+            (PieceKind::Str, "\\\""),
+            (PieceKind::Str, "\""),
+            (PieceKind::CommentMarker, "//"),
+            (PieceKind::Comment, " "),
+            (PieceKind::Comment, "d"),
+            (PieceKind::LineBreak, "\n"),
+        ]);
+    }
+
+    #[test]
+    fn lua_block_comments() {
+        assert_eq!(strip("a\n--[[ one\ntwo ]] b\n-- c\n", "lua"), "a\n\n b\n\n");
+        assert_eq!(only("--[[ one\ntwo ]]\n", "lua"), " one\ntwo\n");
+    }
+
+    #[test]
+    fn unclosed_strings_end_with_their_line() {
+        let yaml = "title: It's here\n# shared comment\n";
+
+        assert_eq!(only(yaml, "yml"), "\n shared comment\n");
+        assert_eq!(strip(yaml, "yml"), "title: It's here\n\n");
+    }
+
+    #[test]
+    fn other_syntaxes() {
+        assert_eq!(strip("SELECT 1; -- one\n", "sql"), "SELECT 1;\n");
+        assert_eq!(strip("a {- b -} c\n", "hs"), "a  c\n");
+        assert_eq!(strip("<p><!-- a --></p>\n", "html"), "<p></p>\n");
+        assert_eq!(strip("(a) ; b\n", "lisp"), "(a)\n");
+        assert_eq!(strip("key = 1 ; b\n", "ini"), "key = 1\n");
+        assert_eq!(strip("$x = 1 <# a #>\n", "ps1"), "$x = 1\n");
+        assert_eq!(strip("=begin\na\n=end\nb # c\n", "rb"), "\n\n\nb\n");
+        assert_eq!(strip("a % b\n", "tex"), "a\n");
+    }
+
+    #[test]
+    fn unknown_languages() {
+        let mut options = Options {
+            comments: CommentFilter::Strip,
+            ..Options::default()
+        };
+
+        assert_eq!(comparison_text(Path::new("a.xyz"), "a // b\n", &options), "a // b\n");
+
+        options.comments = CommentFilter::Only;
+
+        assert_eq!(comparison_text(Path::new("a.xyz"), "a // b\nc\n", &options), "\n\n");
+    }
+}
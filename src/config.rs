@@ -0,0 +1,363 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use toml::{Table, Value};
+
+/// Looked for in DIRECTORY when no `--config` is given
+pub const CONFIG_FILE_NAME: &str = ".strainer.toml";
+
+/// The options that `[[override]]` sections can set, since they only affect
+/// how lines are normalized
+pub const OVERRIDABLE_OPTIONS: &[&str] = &[
+    "trim_whitespace", "collapse_whitespace", "ignore_case", "unicode_form", "strip_trailing_comment",
+    "replace", "squash_chars",
+];
+
+/// The overridable options that are flags rather than taking values
+const OVERRIDABLE_FLAGS: &[&str] = &["trim_whitespace", "collapse_whitespace", "ignore_case"];
+
+/// Other names options can be given by, and the options they stand for
+pub const OPTION_ALIASES: &[(&str, &str)] = &[("include", "path_pattern")];
+
+/// Options that only make sense on the command line
+const CLI_ONLY_OPTIONS: &[&str] = &["DIRECTORY", "config"];
+
+/// Options that take paths to files, which are relative to the config file's
+/// directory
+const FILE_OPTIONS: &[&str] = &["baseline", "write_baseline"];
+
+/// Options that take globs. On the command line these are relative to
+/// DIRECTORY, but in a config file they're relative to its directory.
+const GLOB_OPTIONS: &[&str] = &["path_pattern", "exclude", "priority"];
+
+/// A parsed `.strainer.toml`. Every key is the name of a command-line option
+/// (without the leading dashes), and its value is given the same way it
+/// would be there: `true` for flags, a string or number for options that take
+/// a value, and an array for options that can be given multiple times
+/// (`replace` takes an array of `[REGEX, REPLACEMENT]` pairs). Paths and
+/// globs are relative to the directory the file is in.
+#[derive(Debug)]
+pub struct Config {
+    pub path: PathBuf,
+    pub settings: Table,
+    pub overrides: Vec<ConfigOverride>,
+}
+
+/// An `[[override]]` section: different normalization options for the files
+/// matching any of `paths`
+#[derive(Debug)]
+pub struct ConfigOverride {
+    pub paths: Vec<String>,
+    pub settings: Table,
+}
+
+impl Config {
+    /// Read the config file at `path`, for searching `root`
+    pub fn read(path: &Path, root: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        let mut settings = text.parse::<Table>()
+            .map_err(|e| format!("Couldn't parse {}: {}", path.display(), e))?;
+
+        for &(alias, option) in OPTION_ALIASES {
+            if let Some(value) = settings.remove(alias) {
+                if settings.contains_key(option) {
+                    return Err(format!("In {}: '{}' and '{}' are the same option, so only one can be set", path.display(), alias, option));
+                }
+
+                settings.insert(String::from(option), value);
+            }
+        }
+
+        let config_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let glob_prefix = glob_prefix(config_dir, root);
+        resolve_paths(&mut settings, config_dir, glob_prefix.as_deref());
+
+        let mut overrides = match settings.remove("override") {
+            Some(Value::Array(sections)) => sections.into_iter()
+                .map(ConfigOverride::from_value)
+                .collect::<Result<Vec<ConfigOverride>, String>>()
+                .map_err(|e| format!("In {}: {}", path.display(), e))?,
+            Some(_) => return Err(format!("In {}: 'override' must be an array of tables ([[override]])", path.display())),
+            None => vec![],
+        };
+
+        if let Some(prefix) = &glob_prefix {
+            for config_override in &mut overrides {
+                config_override.paths = config_override.paths.iter()
+                    .map(|glob| prefix_glob(prefix, glob))
+                    .collect();
+            }
+        }
+
+        if let Some(key) = settings.keys().find(|key| CLI_ONLY_OPTIONS.contains(&key.as_str())) {
+            return Err(format!("In {}: '{}' can only be given on the command line", path.display(), key));
+        }
+
+        Ok(Config {
+            path: path.to_path_buf(),
+            settings,
+            overrides,
+        })
+    }
+}
+
+impl ConfigOverride {
+    fn from_value(value: Value) -> Result<Self, String> {
+        let mut settings = match value {
+            Value::Table(settings) => settings,
+            _ => return Err(String::from("each [[override]] must be a table")),
+        };
+
+        let paths = match settings.remove("paths") {
+            Some(Value::String(glob)) => vec![glob],
+            Some(Value::Array(globs)) => globs.into_iter()
+                .map(|glob| match glob {
+                    Value::String(glob) => Ok(glob),
+                    _ => Err(String::from("'paths' in [[override]] must be strings")),
+                })
+                .collect::<Result<Vec<String>, String>>()?,
+            _ => return Err(String::from("each [[override]] needs 'paths', a glob or an array of globs")),
+        };
+
+        if let Some(key) = settings.keys().find(|key| !OVERRIDABLE_OPTIONS.contains(&key.as_str())) {
+            return Err(format!(
+                "'{}' can't be set in [[override]]; only {} can",
+                key,
+                OVERRIDABLE_OPTIONS.join(", "),
+            ));
+        }
+
+        for (name, value) in &settings {
+            override_values(name, value)?;
+        }
+
+        Ok(ConfigOverride { paths, settings })
+    }
+
+    /// The values `name` is set to in this section, the way they'd be given
+    /// on the command line: none for a flag, and in order for an option that
+    /// can be given multiple times. `None` if it isn't set, or is set to
+    /// `false`.
+    pub fn values_of(&self, name: &str) -> Option<Vec<String>> {
+        // Every setting was checked when the section was read
+        self.settings.get(name).and_then(|value| override_values(name, value).unwrap())
+    }
+}
+
+/// Check that `value` is something `name` can be set to in an override, and
+/// turn it into the values it stands for (see `ConfigOverride::values_of`)
+fn override_values(name: &str, value: &Value) -> Result<Option<Vec<String>>, String> {
+    let invalid = || format!("Unsupported value for '{}' in [[override]]", name);
+    let is_flag = OVERRIDABLE_FLAGS.contains(&name);
+
+    let string = |value: &Value| match value {
+        Value::String(s) => Ok(s.clone()),
+        _ => Err(invalid()),
+    };
+
+    let values = match value {
+        Value::Boolean(set) if is_flag => return Ok(Some(vec![]).filter(|_| *set)),
+        Value::String(s) if !is_flag => vec![s.clone()],
+        Value::Array(values) if !is_flag => {
+            let mut strings = Vec::new();
+
+            for value in values {
+                match value {
+                    Value::Array(group) => strings.extend(group.iter().map(string).collect::<Result<Vec<String>, String>>()?),
+                    value => strings.push(string(value)?),
+                }
+            }
+
+            strings
+        },
+        _ => return Err(invalid()),
+    };
+
+    match name {
+        "unicode_form" if !(values.len() == 1 && ["nfc", "nfkc"].contains(&values[0].as_str())) =>
+            Err(String::from("'unicode_form' must be \"nfc\" or \"nfkc\"")),
+        "replace" if values.len() % 2 != 0 =>
+            Err(String::from("'replace' must be an array of [REGEX, REPLACEMENT] pairs")),
+        _ => Ok(Some(values)),
+    }
+}
+
+/// Make the paths in `settings` relative to `config_dir`, and prefix its
+/// globs with `glob_prefix` if it has one
+fn resolve_paths(settings: &mut Table, config_dir: &Path, glob_prefix: Option<&str>) {
+    for (name, value) in settings.iter_mut() {
+        if FILE_OPTIONS.contains(&name.as_str()) {
+            if let Value::String(path) = value {
+                *path = config_dir.join(&path).to_string_lossy().into_owned();
+            }
+        } else if let (true, Some(prefix)) = (GLOB_OPTIONS.contains(&name.as_str()), glob_prefix) {
+            match value {
+                Value::String(glob) => *glob = prefix_glob(prefix, glob),
+                Value::Array(globs) => for glob in globs {
+                    if let Value::String(glob) = glob {
+                        *glob = prefix_glob(prefix, glob);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+/// What to put in front of globs in a config file in `config_dir` to make
+/// them relative to `root`, or `None` if they don't need anything. Globs in a
+/// config file outside of `root` are left relative to it, since they couldn't
+/// match anything otherwise.
+fn glob_prefix(config_dir: &Path, root: &Path) -> Option<String> {
+    let config_dir = match config_dir.as_os_str().is_empty() {
+        true => fs::canonicalize(".").ok()?,
+        false => fs::canonicalize(config_dir).ok()?,
+    };
+    let root = fs::canonicalize(root).ok()?;
+
+    let components = config_dir.strip_prefix(&root).ok()?
+        .iter()
+        .map(|component| component.to_str().map(globset::escape))
+        .collect::<Option<Vec<String>>>()?;
+
+    return Some(components.join("/")).filter(|prefix| !prefix.is_empty());
+}
+
+fn prefix_glob(prefix: &str, glob: &str) -> String {
+    format!("{}/{}", prefix, glob.trim_start_matches('/'))
+}
+
+/// Turn config settings into the equivalent command-line arguments, leaving
+/// out the options for which `skip` returns true
+pub fn settings_to_args(settings: &Table, skip: impl Fn(&str) -> bool) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+
+    for (name, value) in settings.iter().filter(|(name, _)| !skip(name)) {
+        match value {
+            Value::Array(values) => {
+                for value in values {
+                    push_arg(&mut args, name, value)?;
+                }
+            },
+            value => push_arg(&mut args, name, value)?,
+        }
+    }
+
+    return Ok(args);
+}
+
+fn push_arg(args: &mut Vec<String>, name: &str, value: &Value) -> Result<(), String> {
+    match value {
+        Value::Boolean(true) => args.push(format!("--{}", name)),
+        Value::Boolean(false) => {},
+        Value::String(s) => args.push(format!("--{}={}", name, s)),
+        Value::Integer(n) => args.push(format!("--{}={}", name, n)),
+        Value::Float(n) => args.push(format!("--{}={}", name, n)),
+        Value::Array(values) => {
+            args.push(format!("--{}", name));
+
+            for value in values {
+                match value {
+                    Value::String(s) => args.push(s.clone()),
+                    _ => return Err(format!("Values of '{}' must be strings", name)),
+                }
+            }
+        },
+        _ => return Err(format!("Unsupported value for '{}'", name)),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_config(dir: &Path, config_path: &str, text: &str) -> Result<Config, String> {
+        let path = dir.join(config_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, text).unwrap();
+
+        Config::read(&path, dir)
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = read_config(dir.path(), "sub/.strainer.toml", "baseline = \"base.json\"\nwrite_baseline = \"/tmp/out.json\"").unwrap();
+
+        assert_eq!(config.settings["baseline"].as_str().unwrap(), dir.path().join("sub/base.json").to_str().unwrap());
+        assert_eq!(config.settings["write_baseline"].as_str().unwrap(), "/tmp/out.json");
+    }
+
+    #[test]
+    fn globs_are_relative_to_the_config_file_inside_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = read_config(dir.path(), "sub/.strainer.toml", r#"
+            exclude = ["vendor/", "/*.min.js"]
+            priority = "**/*.rs"
+
+            [[override]]
+            paths = "*.md"
+            ignore_case = true
+        "#).unwrap();
+
+        assert_eq!(config.settings["exclude"].as_array().unwrap(), &vec![Value::from("sub/vendor/"), Value::from("sub/*.min.js")]);
+        assert_eq!(config.settings["priority"].as_str().unwrap(), "sub/**/*.rs");
+        assert_eq!(config.overrides[0].paths, vec!["sub/*.md"]);
+    }
+
+    #[test]
+    fn globs_are_unchanged_in_the_root_or_outside_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir(&root).unwrap();
+
+        let in_root = read_config(&root, ".strainer.toml", "exclude = \"vendor/\"").unwrap();
+        let outside = read_config(&root, "../other.toml", "exclude = \"vendor/\"").unwrap();
+
+        assert_eq!(in_root.settings["exclude"].as_str().unwrap(), "vendor/");
+        assert_eq!(outside.settings["exclude"].as_str().unwrap(), "vendor/");
+    }
+
+    #[test]
+    fn aliases_are_read_as_the_options_they_stand_for() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = read_config(dir.path(), ".strainer.toml", "include = \"src/\"").unwrap();
+
+        assert_eq!(config.settings.get("include"), None);
+        assert_eq!(config.settings["path_pattern"].as_str().unwrap(), "src/");
+        assert!(read_config(dir.path(), ".strainer.toml", "include = \"a\"\npath_pattern = \"b\"").is_err());
+    }
+
+    #[test]
+    fn override_values_are_given_as_on_the_command_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = read_config(dir.path(), ".strainer.toml", r#"
+            [[override]]
+            paths = "*"
+            trim_whitespace = true
+            ignore_case = false
+            unicode_form = "nfc"
+            replace = [["a", "b"], ["c", "d"]]
+        "#).unwrap();
+        let config_override = &config.overrides[0];
+
+        assert_eq!(config_override.values_of("trim_whitespace"), Some(vec![]));
+        assert_eq!(config_override.values_of("ignore_case"), None);
+        assert_eq!(config_override.values_of("collapse_whitespace"), None);
+        assert_eq!(config_override.values_of("unicode_form"), Some(vec![String::from("nfc")]));
+        assert_eq!(config_override.values_of("replace").unwrap(), vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn invalid_override_values_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for setting in &["ignore_case = \"yes\"", "unicode_form = \"nfd\"", "replace = [\"a\"]", "squash_chars = 1", "blocks = true"] {
+            let text = format!("[[override]]\npaths = \"*\"\n{}", setting);
+
+            assert!(read_config(dir.path(), ".strainer.toml", &text).is_err(), "{}", setting);
+        }
+    }
+}
@@ -1,6 +1,6 @@
-use std::{collections::{HashMap, HashSet}, fmt::Display, path::{Path, PathBuf}};
+use std::{collections::{HashMap, HashSet}, fmt::Display, io::{self, BufRead, Write}, path::{Path, PathBuf}};
 
-use crate::{options::Options, pattern::matches};
+use crate::{comments::comparison_text, options::{Keep, Options}, pattern::matches, suppress::{Suppressor, suppressed_lines}};
 
 pub type Occurrences = HashMap<String, Vec<FileLocation>>;
 
@@ -12,91 +12,311 @@ pub fn count_lines(
 ) -> Occurrences {
     let mut records = HashMap::new();
     let mut current_line_number = 0;
+    let comparison = comparison_text(file_path, text, options);
+    let suppressed = suppressed_lines(file_path, text, options.line_delimiter);
 
-    walk_lines(text, options,
+    walk_lines(&comparison, options,
         |next| {
             if let CharOrLine::Line(line) = next {
                 current_line_number += 1;
 
-                record_line(
-                    &options,
-                    &mut records,
-                    file_path,
-                    &line,
-                    current_line_number,
-                );
+                if !suppressed[current_line_number - 1] {
+                    record_line(
+                        options,
+                        &mut records,
+                        file_path,
+                        &line,
+                        current_line_number,
+                    );
+                }
             }
         });
 
     return records;
 }
 
-/// Return a copy of `text` with all duplicate lines removed (the first 
-/// instance remains)
+/// The raw forms of each key (normalized line) in a file, and how many times
+/// each of them appears
+pub type LineForms = HashMap<String, HashMap<String, usize>>;
+
+/// Return the keys that `strip_lines` would compare for `text`, along with
+/// the raw forms of each
+pub fn line_forms(
+    file_path: &Path,
+    text: &str,
+    options: &Options,
+) -> LineForms {
+    let mut forms: LineForms = HashMap::new();
+
+    for (key, raw_line) in line_keys(file_path, text, options).into_iter().zip(text.split(options.line_delimiter)) {
+        if let Some(key) = key {
+            *forms.entry(key).or_default().entry(raw_line.to_owned()).or_insert(0) += 1;
+        }
+    }
+
+    return forms;
+}
+
+/// Decide which file keeps each line when removing duplicates across files.
+/// `files` holds the line forms of each file, in priority order. Returns, for
+/// each file, the keys it has to remove entirely because another file keeps
+/// them, and with `Keep::MostCommon` the raw form chosen for each key.
+pub fn claim_lines(files: &[LineForms], keep: Keep) -> (Vec<HashSet<String>>, HashMap<String, String>) {
+    let mut owners: HashMap<&str, usize> = HashMap::new();
+    let mut preferred_forms = HashMap::new();
+
+    match keep {
+        Keep::First => for (index, forms) in files.iter().enumerate().rev() {
+            owners.extend(forms.keys().map(|key| (key.as_str(), index)));
+        },
+        Keep::Last => for (index, forms) in files.iter().enumerate() {
+            owners.extend(forms.keys().map(|key| (key.as_str(), index)));
+        },
+        Keep::MostCommon => {
+            // key -> raw form -> (total count, first file it appears in)
+            let mut totals: HashMap<&str, HashMap<&str, (usize, usize)>> = HashMap::new();
+
+            for (index, forms) in files.iter().enumerate() {
+                for (key, key_forms) in forms {
+                    for (raw, count) in key_forms {
+                        let total = totals.entry(key.as_str()).or_default().entry(raw.as_str()).or_insert((0, index));
+                        total.0 += count;
+                    }
+                }
+            }
+
+            for (key, key_totals) in totals {
+                let (raw, (_, owner)) = key_totals.into_iter()
+                    .min_by_key(|&(raw, (count, first_file))| (std::cmp::Reverse(count), first_file, raw))
+                    .unwrap();
+
+                owners.insert(key, owner);
+                preferred_forms.insert(key.to_owned(), raw.to_owned());
+            }
+        },
+    }
+
+    let claimed_elsewhere = files.iter()
+        .enumerate()
+        .map(|(index, forms)| forms.keys()
+            .filter(|key| owners[key.as_str()] != index)
+            .cloned()
+            .collect())
+        .collect();
+
+    return (claimed_elsewhere, preferred_forms);
+}
+
+/// Return a copy of `text` with all duplicate lines removed, and the number
+/// of lines removed. Which instance of a line remains is up to
+/// `options.keep`. Lines are compared after normalization; lines that
+/// normalize to nothing, don't pass the line filters or are suppressed (see
+/// `suppress`) are never removed.
+///
+/// Lines whose keys are in `claimed_elsewhere` (because another file keeps
+/// them) are removed from this file entirely. With `Keep::MostCommon`, the
+/// raw form to keep for a key can be given in `preferred_forms`; otherwise
+/// it is the most frequent one in this file.
 pub fn strip_lines(
+    file_path: &Path,
     text: &str,
     options: &Options,
-) -> String {
-    let mut found_lines = HashSet::new();
+    claimed_elsewhere: &HashSet<String>,
+    preferred_forms: &HashMap<String, String>,
+) -> (String, usize) {
+    let keys = line_keys(file_path, text, options);
+    let raw_lines = text.split(options.line_delimiter).collect::<Vec<&str>>();
+    let keepers = keeper_lines(&keys, &raw_lines, options.keep, preferred_forms);
+
     let mut new_text = String::new();
+    let mut removed_prev_line = false;
+    let mut removed_count = 0;
+    let mut line_index = 0;
 
     walk_lines(text, options,
         |next| {
             match next {
-                CharOrLine::Char(ch) => new_text.push(ch),
+                CharOrLine::Char(ch) => {
+                    // the delimiter after a removed line goes with it
+                    if !removed_prev_line {
+                        new_text.push(ch);
+                    }
+                },
                 CharOrLine::Line(line) => {
-                    if found_lines.contains(&line) {
-                        // do nothing
+                    removed_prev_line = match &keys[line_index] {
+                        Some(key) => claimed_elsewhere.contains(key) || keepers[key.as_str()] != line_index,
+                        None => false,
+                    };
+
+                    if removed_prev_line {
+                        removed_count += 1;
                     } else {
                         new_text.push_str(&line);
-                        found_lines.insert(line);
                     }
+
+                    line_index += 1;
                 }
             }
         });
 
-    return new_text;
+    return (new_text, removed_count);
 }
 
-enum CharOrLine {
+/// Like `strip_lines`, but read lines from `input` and write the ones that
+/// remain to `output` as they go, without holding the whole text in memory.
+/// The first instance of each line is kept, and comments aren't recognized
+/// since there's no file extension to go by. Suppression markers work as in a
+/// file, except that `IGNORE_FILE` only applies from its line on, and markers
+/// count anywhere on a line. Lines are compared with invalid UTF-8 replaced by
+/// U+FFFD, but the ones that remain are written exactly as they were read,
+/// and flushed one at a time so that output keeps up with slow input. Returns
+/// the number of lines removed.
+pub fn strip_lines_streaming(
+    input: impl BufRead,
+    mut output: impl Write,
+    options: &Options,
+) -> io::Result<usize> {
+    let mut found_lines = HashSet::new();
+    let mut suppressor = Suppressor::new();
+    let mut removed_count = 0;
+
+    walk_lines_from(input, options,
+        |raw_line, delimiter| {
+            let line = String::from_utf8_lossy(raw_line);
+            let normalized = options.normalizer.normalize(&line);
+
+            let removed = !suppressor.is_suppressed(&line)
+                && !normalized.is_empty()
+                && line_is_included(options, &normalized)
+                && !found_lines.insert(normalized);
+
+            if removed {
+                removed_count += 1;
+                return Ok(());
+            }
+
+            output.write_all(raw_line)?;
+            output.write_all(delimiter)?;
+
+            return output.flush();
+        })?;
+
+    return Ok(removed_count);
+}
+
+/// The key each line of `text` is compared by in remove mode, or `None` if
+/// the line is never removed
+fn line_keys(file_path: &Path, text: &str, options: &Options) -> Vec<Option<String>> {
+    comparison_text(file_path, text, options)
+        .split(options.line_delimiter)
+        .zip(suppressed_lines(file_path, text, options.line_delimiter))
+        .map(|(line, suppressed)| match suppressed {
+            true => None,
+            false => Some(options.normalizer_for(file_path).normalize(line)),
+        })
+        .map(|normalized| normalized.filter(|key| !key.is_empty() && line_is_included(options, key)))
+        .collect()
+}
+
+/// For each key, the index of the line that `keep` says should remain
+fn keeper_lines<'a>(
+    keys: &'a [Option<String>],
+    raw_lines: &[&str],
+    keep: Keep,
+    preferred_forms: &HashMap<String, String>,
+) -> HashMap<&'a str, usize> {
+    let keyed_lines = keys.iter()
+        .enumerate()
+        .filter_map(|(index, key)| key.as_deref().map(|key| (index, key)));
+
+    let mut keepers = HashMap::new();
+
+    match keep {
+        Keep::First => for (index, key) in keyed_lines {
+            keepers.entry(key).or_insert(index);
+        },
+        Keep::Last => keepers.extend(keyed_lines.map(|(index, key)| (key, index))),
+        Keep::MostCommon => {
+            // key -> raw form -> (count, first line with that form)
+            let mut forms: HashMap<&str, HashMap<&str, (usize, usize)>> = HashMap::new();
+
+            for (index, key) in keyed_lines {
+                forms.entry(key).or_default().entry(raw_lines[index]).or_insert((0, index)).0 += 1;
+            }
+
+            for (key, key_forms) in forms {
+                let preferred = preferred_forms.get(key)
+                    .and_then(|raw| key_forms.get(raw.as_str()));
+
+                let (_, first_line) = match preferred {
+                    Some(&form) => form,
+                    None => key_forms.into_values()
+                        .min_by_key(|&(count, first_line)| (std::cmp::Reverse(count), first_line))
+                        .unwrap(),
+                };
+
+                keepers.insert(key, first_line);
+            }
+        },
+    }
+
+    return keepers;
+}
+
+pub enum CharOrLine {
     Char(char),
     Line(String),
 }
 
-/// Walk through the lines in `text`, following the specified behavior from
-/// `options`, and do something on each line and each character between lines
-fn walk_lines(
+/// Walk through the lines in `text`, split according to `options`, and do
+/// something on each (raw, un-normalized) line and each delimiter character
+/// between lines
+pub fn walk_lines(
     text: &str,
     options: &Options,
-    mut handle_next: impl FnMut(CharOrLine) -> (),
+    mut handle_next: impl FnMut(CharOrLine),
 ) {
     let mut current_line = String::new();
-    let mut prev_char: Option<char> = None;
 
     for c in text.chars() {
-        let squashing = prev_char
-            .map(|prev| prev == c && options.squash_chars.contains(&prev))
-            .unwrap_or(false);
-
-        if squashing {
-            handle_next(CharOrLine::Char(c));
-        } else if c == options.line_delimiter {
-            let completed_line = current_line;
-            current_line = String::new();
+        if c == options.line_delimiter {
+            let completed_line = std::mem::take(&mut current_line);
 
             handle_next(CharOrLine::Line(completed_line));
             handle_next(CharOrLine::Char(c));
         } else {
             current_line.push(c);
-            handle_next(CharOrLine::Char(c));
         }
-
-        prev_char = Some(c);
     }
 
     handle_next(CharOrLine::Line(current_line));
 }
 
+/// Read `input` one line at a time, passing each line's raw bytes to
+/// `handle_line` along with the delimiter that ended it (which is empty for
+/// the last line). Stops at the first error returned by `handle_line`.
+pub fn walk_lines_from(
+    mut input: impl BufRead,
+    options: &Options,
+    mut handle_line: impl FnMut(&[u8], &[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut encoded = [0; 4];
+    let delimiter = options.line_delimiter.encode_utf8(&mut encoded).as_bytes();
+    let mut buffer = Vec::new();
+
+    loop {
+        let read = input.read_until(delimiter[delimiter.len() - 1], &mut buffer)?;
+
+        if buffer.ends_with(delimiter) {
+            handle_line(&buffer[..buffer.len() - delimiter.len()], delimiter)?;
+
+            buffer.clear();
+        } else if read == 0 {
+            return handle_line(&buffer, &[]);
+        }
+    }
+}
+
 // pub fn count_lines(
 //     file_path: &Path,
 //     text: &str,
@@ -152,7 +372,7 @@ pub fn merge_records(
 ) {
     let mut source = source;
     
-    let source_keys = source.keys().map(|k| k.clone()).collect::<Vec<String>>();
+    let source_keys = source.keys().cloned().collect::<Vec<String>>();
 
     for key in source_keys {
         let mut source_val = source.remove(&key).unwrap();
@@ -166,6 +386,7 @@ pub fn merge_records(
     }
 }
 
+/// Normalize `line` and record it
 pub fn record_line(
     options: &Options,
     records: &mut Occurrences,
@@ -173,16 +394,31 @@ pub fn record_line(
     line: &str,
     line_number: usize,
 ) {
-    let line = if options.trim_whitespace {
-        String::from(line.trim())
-    } else {
-        line.to_owned()
-    };
+    record_normalized_line(
+        options,
+        records,
+        file_path,
+        options.normalizer_for(file_path).normalize(line),
+        line_number,
+    );
+}
 
-    if line.len() > 0 && matches(&line, &options.line_pattern) {
+/// Record a line that has already been normalized, if it
+/// passes the line filters
+pub fn record_normalized_line(
+    options: &Options,
+    records: &mut Occurrences,
+    file_path: &Path,
+    line: String,
+    line_number: usize,
+) {
+    if !line.is_empty() && line_is_included(options, &line) {
         let file_location = FileLocation {
             path: PathBuf::from(file_path),
             line_number,
+            column: 1,
+            end_line_number: line_number,
+            raw_text: None,
         };
 
         match records.get_mut(&line) {
@@ -194,42 +430,184 @@ pub fn record_line(
     }
 }
 
-/// A fully-qualified line location within a file (file path + line number)
+/// Whether `line` passes the line pattern and the include/exclude regexes.
+/// A line must match at least one include regex (if any were given) and none
+/// of the exclude regexes.
+pub fn line_is_included(options: &Options, line: &str) -> bool {
+    matches(line, &options.line_pattern)
+        && (options.line_regexes.is_empty() || options.line_regexes.iter().any(|re| re.is_match(line)))
+        && !options.exclude_line_regexes.iter().any(|re| re.is_match(line))
+}
+
+/// A fully-qualified location within a file (file path + line number). Runs
+/// of several lines also have the number of the last line they include;
+/// single lines have an `end_line_number` equal to their `line_number`.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct FileLocation {
     pub path: PathBuf,
     pub line_number: usize,
+    /// Column (in characters, starting at 1) where the occurrence begins.
+    /// Occurrences of whole lines always begin at column 1.
+    pub column: usize,
+    pub end_line_number: usize,
+    /// The original text found here, if the key it was recorded under
+    /// abstracts it away (ie. with `--parameterize`)
+    pub raw_text: Option<String>,
 }
 
 impl Display for FileLocation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}:{}", self.path.to_str().unwrap(), self.line_number)
+        if self.end_line_number > self.line_number {
+            write!(f, "{}:{}-{}", self.path.display(), self.line_number, self.end_line_number)
+        } else {
+            write!(f, "{}:{}", self.path.display(), self.line_number)
+        }
     }
 }
 
-// #[cfg(test)]
-// mod tests {
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalize::{NormalizeStep, Normalizer};
+    use regex::Regex;
 
-//     #[test]
-//     fn test_strip_lines_with_no_duplicates() {
-//         let s = "[Adblock Plus 2.0]
-//     ||apps.facebook.com^
-//     ||apps.facebook.com^$popup
-//     ||apps.facebook.com^$third-party";
+    fn options(keep: Keep) -> Options {
+        Options {
+            keep,
+            ..Options::default()
+        }
+    }
 
-//         let stripped = strip_lines(s, &(CountingOptions {
-//             line_delimiter: '\n',
-//             squash_chars: vec![],
+    fn strip(text: &str, options: &Options) -> (String, usize) {
+        strip_lines(Path::new("file.txt"), text, options, &HashSet::new(), &HashMap::new())
+    }
 
-//             // aren't used by this function
-//             line_pattern: vec![],
-//             ignore_delimiters: vec![],
-//             trim_whitespace: false,
-//             same_file: false,
-//             remove_duplicates: false,
-//         }));
+    fn forms(text: &str, options: &Options) -> LineForms {
+        line_forms(Path::new("file.txt"), text, options)
+    }
 
-//         assert_eq!(s, stripped);
-//     }
+    #[test]
+    fn strip_lines_with_no_duplicates() {
+        let s = "[Adblock Plus 2.0]
+    ||apps.facebook.com^
+    ||apps.facebook.com^$popup
+    ||apps.facebook.com^$third-party";
 
-// }
+        assert_eq!(strip(s, &options(Keep::First)), (s.to_owned(), 0));
+    }
+
+    #[test]
+    fn keep_first() {
+        assert_eq!(strip("a\nb\na\nc\nb\n", &options(Keep::First)), ("a\nb\nc\n".to_owned(), 2));
+    }
+
+    #[test]
+    fn keep_last() {
+        assert_eq!(strip("a\nb\na\nc\nb\n", &options(Keep::Last)), ("a\nc\nb\n".to_owned(), 2));
+        assert_eq!(strip("a\nb\na", &options(Keep::Last)), ("b\na".to_owned(), 1));
+    }
+
+    #[test]
+    fn keep_most_common() {
+        let options = Options {
+            normalizer: Normalizer::new(vec![NormalizeStep::Trim]),
+            ..options(Keep::MostCommon)
+        };
+
+        assert_eq!(strip("  x\ny\nx\n x \nx\n", &options), ("y\nx\n".to_owned(), 3));
+
+        // Ties go to the form that appears first
+        assert_eq!(strip(" x\nx\n", &options), (" x\n".to_owned(), 1));
+    }
+
+    #[test]
+    fn preferred_forms_win_over_counts() {
+        let options = Options {
+            normalizer: Normalizer::new(vec![NormalizeStep::Trim]),
+            ..options(Keep::MostCommon)
+        };
+        let preferred = vec![("x".to_owned(), " x".to_owned())].into_iter().collect();
+
+        let stripped = strip_lines(Path::new("file.txt"), "x\n x\nx\n", &options, &HashSet::new(), &preferred);
+
+        assert_eq!(stripped, (" x\n".to_owned(), 2));
+    }
+
+    #[test]
+    fn empty_and_filtered_lines_are_never_removed() {
+        assert_eq!(strip("a\n\n\na\n\n", &options(Keep::First)), ("a\n\n\n\n".to_owned(), 1));
+
+        let options = Options {
+            line_regexes: vec![Regex::new("^a").unwrap()],
+            ..options(Keep::Last)
+        };
+
+        assert_eq!(strip("a\nb\na\nb\n", &options), ("b\na\nb\n".to_owned(), 1));
+    }
+
+    #[test]
+    fn suppressed_lines_are_never_removed() {
+        let text = "a\na // strainer-ignore-line\na\n";
+
+        assert_eq!(strip_lines(Path::new("file.rs"), text, &options(Keep::First), &HashSet::new(), &HashMap::new()),
+            ("a\na // strainer-ignore-line\n".to_owned(), 1));
+    }
+
+    #[test]
+    fn claimed_lines_are_removed_entirely() {
+        let claimed = vec!["a".to_owned()].into_iter().collect();
+
+        let stripped = strip_lines(Path::new("file.txt"), "a\nb\na\nb\n", &options(Keep::First), &claimed, &HashMap::new());
+
+        assert_eq!(stripped, ("b\n".to_owned(), 3));
+    }
+
+    #[test]
+    fn streaming_writes_remaining_lines_unchanged() {
+        let mut output = Vec::new();
+        let removed = strip_lines_streaming(&b"caf\xE9\nx\ncaf\xE9\n"[..], &mut output, &options(Keep::First)).unwrap();
+
+        assert_eq!(output, b"caf\xE9\nx\n");
+        assert_eq!(removed, 1);
+    }
+
+    #[test]
+    fn streaming_keeps_the_first_occurrence() {
+        let mut output = Vec::new();
+        let removed = strip_lines_streaming("a\nb\na\n\n\nb".as_bytes(), &mut output, &options(Keep::First)).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "a\nb\n\n\n");
+        assert_eq!(removed, 2);
+    }
+
+    #[test]
+    fn claims_follow_the_keep_policy() {
+        let options = Options {
+            normalizer: Normalizer::new(vec![NormalizeStep::Trim]),
+            ..Options::default()
+        };
+        let files = [
+            forms("a\n b\nc\n", &options),
+            forms("a\nb\n", &options),
+            forms("b\nc\nb\n", &options),
+        ];
+        let claimed = |keep| {
+            claim_lines(&files, keep).0.into_iter()
+                .map(|keys| {
+                    let mut keys = keys.into_iter().collect::<Vec<String>>();
+                    keys.sort();
+                    keys
+                })
+                .collect::<Vec<Vec<String>>>()
+        };
+
+        assert_eq!(claimed(Keep::First), [vec![], vec!["a", "b"], vec!["b", "c"]]);
+        assert_eq!(claimed(Keep::Last), [vec!["a", "b", "c"], vec!["b"], vec![]]);
+
+        // "b" is most often written without a space, and that's first seen
+        // in the second file
+        let (_, preferred) = claim_lines(&files, Keep::MostCommon);
+        assert_eq!(claimed(Keep::MostCommon), [vec!["b"], vec!["a"], vec!["b", "c"]]);
+        assert_eq!(preferred["b"], "b");
+    }
+}
@@ -0,0 +1,284 @@
+use std::{borrow::Cow, fmt};
+
+/// How many bytes at the start of a file are checked for NUL bytes to decide
+/// whether it's binary
+const BINARY_SNIFF_LEN: usize = 8192;
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// In `Encoding::Bytes`, each byte that isn't part of valid UTF-8 is stood in
+/// for by this plus the byte, which lands in a private use area. That keeps
+/// differing bytes distinct when lines are compared, and lets the original
+/// bytes be written back in remove mode. Characters in the file that are
+/// already in this range are read as their bytes too, so every character in
+/// it stands in for a byte.
+const RAW_BYTE_BASE: u32 = 0x10FF00;
+
+/// How the bytes of a file are turned into text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO-8859-1, where every byte is the code point of the same value
+    Latin1,
+    /// UTF-8 where it's valid, and raw bytes (shown as `\xNN` escapes)
+    /// where it isn't
+    Bytes,
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "bytes" => Some(Encoding::Bytes),
+            _ => None,
+        }
+    }
+
+    fn is_utf16(self) -> bool {
+        self == Encoding::Utf16Le || self == Encoding::Utf16Be
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "Latin-1",
+            Encoding::Bytes => "bytes",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// The contents of a file as text, and what's needed to turn it back into
+/// the same kind of bytes
+#[derive(Debug)]
+pub struct DecodedText {
+    pub text: String,
+    pub encoding: Encoding,
+    /// Whether the file started with a byte order mark
+    pub bom: bool,
+}
+
+/// The contents of a file couldn't be read as the encoding it's in
+#[derive(Debug)]
+pub struct DecodeError(pub Encoding);
+
+/// Decode the contents of a file. A byte order mark decides the encoding if
+/// there is one, and otherwise `fallback` is used. Returns `None` for binary
+/// files (ones with a NUL byte near the start, unless they're UTF-16).
+pub fn decode(bytes: &[u8], fallback: Encoding) -> Result<Option<DecodedText>, DecodeError> {
+    let (encoding, bom_len) = if bytes.starts_with(UTF8_BOM) {
+        (Encoding::Utf8, UTF8_BOM.len())
+    } else if bytes.starts_with(UTF16LE_BOM) {
+        (Encoding::Utf16Le, UTF16LE_BOM.len())
+    } else if bytes.starts_with(UTF16BE_BOM) {
+        (Encoding::Utf16Be, UTF16BE_BOM.len())
+    } else {
+        (fallback, 0)
+    };
+    let content = &bytes[bom_len..];
+
+    // UTF-16 text is full of NUL bytes
+    if !encoding.is_utf16() && content.iter().take(BINARY_SNIFF_LEN).any(|&b| b == 0) {
+        return Ok(None);
+    }
+
+    let text = match encoding {
+        Encoding::Utf8 => String::from_utf8(content.to_vec()).map_err(|_| DecodeError(encoding))?,
+        Encoding::Utf16Le | Encoding::Utf16Be => decode_utf16(content, encoding).ok_or(DecodeError(encoding))?,
+        Encoding::Latin1 => content.iter().map(|&b| b as char).collect(),
+        Encoding::Bytes => decode_bytes(content),
+    };
+
+    return Ok(Some(DecodedText {
+        text,
+        encoding,
+        bom: bom_len > 0,
+    }));
+}
+
+impl DecodedText {
+    /// Encode `text` the same way this was encoded, byte order mark and all
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(text.len());
+
+        match self.encoding {
+            Encoding::Utf8 | Encoding::Bytes if self.bom => bytes.extend_from_slice(UTF8_BOM),
+            Encoding::Utf16Le if self.bom => bytes.extend_from_slice(UTF16LE_BOM),
+            Encoding::Utf16Be if self.bom => bytes.extend_from_slice(UTF16BE_BOM),
+            _ => {},
+        }
+
+        match self.encoding {
+            Encoding::Utf8 => bytes.extend_from_slice(text.as_bytes()),
+            Encoding::Utf16Le => bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+            Encoding::Utf16Be => bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+            // Everything in the text came from a single byte
+            Encoding::Latin1 => bytes.extend(text.chars().map(|c| c as u32 as u8)),
+            Encoding::Bytes => {
+                let mut buffer = [0; 4];
+
+                for c in text.chars() {
+                    match raw_byte(c) {
+                        Some(b) => bytes.push(b),
+                        None => bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes()),
+                    }
+                }
+            },
+        }
+
+        return bytes;
+    }
+}
+
+fn decode_utf16(bytes: &[u8], encoding: Encoding) -> Option<String> {
+    let pairs = bytes.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+
+    let units = pairs.map(|pair| match encoding {
+        Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+        _ => u16::from_be_bytes([pair[0], pair[1]]),
+    });
+
+    return std::char::decode_utf16(units).collect::<Result<String, _>>().ok();
+}
+
+/// Decode UTF-8, standing in for any invalid bytes with `RAW_BYTE_BASE`
+/// characters
+fn decode_bytes(mut bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                push_valid_utf8(&mut text, valid);
+                return text;
+            },
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                // Checked by `from_utf8` already
+                push_valid_utf8(&mut text, std::str::from_utf8(valid).unwrap_or_default());
+
+                let invalid_len = e.error_len().unwrap_or(rest.len());
+                text.extend(rest[..invalid_len].iter().map(|&b| stand_in_char(b)));
+                bytes = &rest[invalid_len..];
+            },
+        }
+    }
+}
+
+/// Add `valid` to `text`, except for any characters that would be mistaken for
+/// stand-ins, whose bytes are stood in for instead
+fn push_valid_utf8(text: &mut String, valid: &str) {
+    if raw_byte_chars(valid).next().is_none() {
+        text.push_str(valid);
+        return;
+    }
+
+    for c in valid.chars() {
+        match raw_byte(c) {
+            Some(_) => text.extend(c.encode_utf8(&mut [0; 4]).bytes().map(stand_in_char)),
+            None => text.push(c),
+        }
+    }
+}
+
+fn raw_byte_chars(text: &str) -> impl Iterator<Item = char> + '_ {
+    text.chars().filter(|&c| raw_byte(c).is_some())
+}
+
+fn stand_in_char(byte: u8) -> char {
+    std::char::from_u32(RAW_BYTE_BASE + byte as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// The byte `c` stands in for, if it's one of the `RAW_BYTE_BASE` characters
+fn raw_byte(c: char) -> Option<u8> {
+    (c as u32).checked_sub(RAW_BYTE_BASE).map(|b| b as u8)
+}
+
+/// Show the raw bytes in text read with `Encoding::Bytes` as `\xNN` escapes
+pub fn escape_raw_bytes(text: &str) -> Cow<'_, str> {
+    if raw_byte_chars(text).next().is_none() {
+        return Cow::Borrowed(text);
+    }
+
+    let escaped = text.chars()
+        .map(|c| match raw_byte(c) {
+            Some(b) => format!("\\x{:02X}", b),
+            None => c.to_string(),
+        })
+        .collect();
+
+    return Cow::Owned(escaped);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bytes: &[u8], fallback: Encoding) -> DecodedText {
+        let decoded = decode(bytes, fallback).unwrap().unwrap();
+        assert_eq!(decoded.encode(&decoded.text), bytes);
+
+        return decoded;
+    }
+
+    #[test]
+    fn utf8_round_trips() {
+        assert_eq!(round_trip("héllo\n".as_bytes(), Encoding::Utf8).text, "héllo\n");
+        assert_eq!(round_trip(b"\xEF\xBB\xBFa\n", Encoding::Latin1).text, "a\n");
+    }
+
+    #[test]
+    fn utf16_with_bom_round_trips() {
+        let le = round_trip(b"\xFF\xFEa\x00\n\x00", Encoding::Utf8);
+        let be = round_trip(b"\xFE\xFF\x00a\x00\n", Encoding::Utf8);
+
+        assert_eq!((le.text.as_str(), le.encoding), ("a\n", Encoding::Utf16Le));
+        assert_eq!((be.text.as_str(), be.encoding), ("a\n", Encoding::Utf16Be));
+    }
+
+    #[test]
+    fn latin1_round_trips() {
+        assert_eq!(round_trip(b"caf\xE9\n", Encoding::Latin1).text, "café\n");
+    }
+
+    #[test]
+    fn invalid_bytes_round_trip() {
+        let decoded = round_trip(b"a\xFFb\xC3\n\xE2\x82", Encoding::Bytes);
+
+        assert_eq!(escape_raw_bytes(&decoded.text), "a\\xFFb\\xC3\n\\xE2\\x82");
+    }
+
+    #[test]
+    fn characters_in_the_stand_in_range_round_trip() {
+        // U+10FF41, which would otherwise be mistaken for a stand-in for 'A'
+        let decoded = round_trip(b"k\xF4\x8F\xBD\x81", Encoding::Bytes);
+
+        assert_eq!(escape_raw_bytes(&decoded.text), "k\\xF4\\x8F\\xBD\\x81");
+    }
+
+    #[test]
+    fn invalid_text_is_an_error() {
+        assert!(decode(b"a\xFF", Encoding::Utf8).is_err());
+        assert!(decode(b"\xFF\xFEa", Encoding::Utf8).is_err());
+    }
+
+    #[test]
+    fn nul_bytes_mean_binary() {
+        assert!(decode(b"a\x00b", Encoding::Bytes).unwrap().is_none());
+    }
+}
@@ -0,0 +1,119 @@
+use std::{fmt, io, path::{Path, PathBuf}};
+
+use crate::encoding::{DecodeError, Encoding};
+
+/// Something that stops a scan from going ahead at all
+#[derive(Debug)]
+pub enum ScanError {
+    /// One of the roots doesn't exist or can't be read
+    Root(PathBuf, io::Error),
+    ReadBaseline(PathBuf, io::Error),
+    WriteBaseline(PathBuf, io::Error),
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanError::Root(path, e) => write!(f, "Couldn't search {}: {}", path.display(), e),
+            ScanError::ReadBaseline(path, e) => write!(f, "Couldn't read baseline {}: {}", path.display(), e),
+            ScanError::WriteBaseline(path, e) => write!(f, "Couldn't write baseline {}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+/// A file or directory that couldn't be searched (or in remove mode,
+/// rewritten). The rest of the scan carries on without it.
+#[derive(Debug)]
+pub struct FileError {
+    pub path: PathBuf,
+    pub kind: FileErrorKind,
+}
+
+#[derive(Debug)]
+pub enum FileErrorKind {
+    PermissionDenied,
+    /// The file isn't valid text in the encoding it was read as
+    InvalidEncoding(Encoding),
+    Io(io::Error),
+    /// Listing a directory failed some other way (eg. a symlink loop)
+    Walk(ignore::Error),
+}
+
+impl FileError {
+    pub fn new(path: &Path, kind: FileErrorKind) -> Self {
+        FileError {
+            path: path.to_path_buf(),
+            kind,
+        }
+    }
+
+    /// An error from walking `root`. Where the walker knows which path it
+    /// was at, that's used instead of `root`.
+    pub fn from_walk(root: &Path, error: ignore::Error) -> Self {
+        let path = walk_error_path(&error).unwrap_or(root).to_path_buf();
+
+        let kind = match error.io_error().map(io::Error::kind) {
+            Some(io::ErrorKind::PermissionDenied) => FileErrorKind::PermissionDenied,
+            _ => FileErrorKind::Walk(error),
+        };
+
+        FileError {
+            path,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.kind)
+    }
+}
+
+impl std::error::Error for FileError {}
+
+impl fmt::Display for FileErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileErrorKind::PermissionDenied => write!(f, "permission denied"),
+            FileErrorKind::InvalidEncoding(encoding) => write!(f, "not valid {}", encoding),
+            FileErrorKind::Io(e) => write!(f, "{}", e),
+            FileErrorKind::Walk(e) => write!(f, "{}", without_location(e)),
+        }
+    }
+}
+
+impl From<io::Error> for FileErrorKind {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => FileErrorKind::PermissionDenied,
+            _ => FileErrorKind::Io(error),
+        }
+    }
+}
+
+impl From<DecodeError> for FileErrorKind {
+    fn from(error: DecodeError) -> Self {
+        FileErrorKind::InvalidEncoding(error.0)
+    }
+}
+
+fn walk_error_path(error: &ignore::Error) -> Option<&Path> {
+    match error {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => walk_error_path(err),
+        ignore::Error::Loop { child, .. } => Some(child),
+        _ => None,
+    }
+}
+
+/// The walker wraps errors with the path and depth they happened at, which
+/// `FileError` already shows
+fn without_location(error: &ignore::Error) -> &ignore::Error {
+    match error {
+        ignore::Error::WithPath { err, .. } | ignore::Error::WithDepth { err, .. } => without_location(err),
+        _ => error,
+    }
+}
@@ -1,30 +1,78 @@
-use std::io::{self};
-use std::{
-    fs,
-    path::{Path, PathBuf}, collections::VecDeque,
-};
+use ignore::WalkBuilder;
+use std::path::{Path, PathBuf};
 
-use crate::pattern::{matches,Pattern};
-
-/// Given a root directory and a pattern to match paths against, recursively
-/// list all files that are to be searched
-pub fn list_files_in_dir(root_dir: &Path, pattern: &Pattern) -> Result<Vec<PathBuf>, io::Error> {
-    let mut dir_queue: VecDeque<PathBuf> = VecDeque::new();
-    dir_queue.push_back(PathBuf::from(root_dir));
+use crate::error::FileError;
+use crate::options::{Options, WalkOptions};
+use crate::pattern::{GlobList, PathFilter};
 
+/// Given a root directory and a filter to match paths against, recursively
+/// list all files that are to be searched. Ignore files and hidden files are
+/// respected according to `walk_options`, and `filter` is then applied to
+/// whatever is left. Directories excluded by `filter` are never entered.
+/// Anything that can't be listed is returned separately, and doesn't stop the
+/// rest from being listed.
+pub fn list_files_in_dir(root_dir: &Path, filter: &PathFilter, walk_options: &WalkOptions) -> (Vec<PathBuf>, Vec<FileError>) {
     let mut file_paths = Vec::new();
+    let mut errors = Vec::new();
+
+    walk_files(root_dir, filter, walk_options, |entry| match entry {
+        Ok(path) => file_paths.push(path),
+        Err(e) => errors.push(e),
+    });
+
+    return (file_paths, errors);
+}
+
+/// Like `list_files_in_dir`, but hand each file (or error) to `f` as soon as
+/// it's found
+pub fn walk_files(root_dir: &Path, filter: &PathFilter, walk_options: &WalkOptions, mut f: impl FnMut(Result<PathBuf, FileError>)) {
+    let root = root_dir.to_path_buf();
+    let dir_filter = filter.clone();
+
+    let walker = WalkBuilder::new(root_dir)
+        .hidden(!walk_options.hidden)
+        .git_ignore(walk_options.git_ignore)
+        .git_global(walk_options.git_ignore)
+        .ignore(walk_options.ignore_files)
+        .git_exclude(walk_options.git_exclude)
+        .parents(walk_options.git_ignore || walk_options.ignore_files)
+        .require_git(false)
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+
+            entry.depth() == 0 || !is_dir || dir_filter.allows_dir(relative_to(&root, entry.path()))
+        })
+        .build();
 
-    while !dir_queue.is_empty() {
-        let next_dir = dir_queue.pop_front().unwrap();
-        
-        for child_path in fs::read_dir(next_dir)?.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
-            if child_path.is_dir() {
-                dir_queue.push_back(PathBuf::from(child_path));
-            } else if matches(child_path.to_str().unwrap(), pattern) {
-                file_paths.push(PathBuf::from(child_path));
-            }
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                f(Err(FileError::from_walk(root_dir, e)));
+                continue;
+            },
+        };
+        let is_file = entry.file_type().map(|t| t.is_file()).unwrap_or(false);
+
+        if is_file && filter.allows_file(relative_to(root_dir, entry.path())) {
+            f(Ok(entry.into_path()));
         }
     }
+}
+
+fn relative_to<'a>(root: &Path, path: &'a Path) -> &'a Path {
+    path.strip_prefix(root).unwrap_or(path)
+}
+
+/// Sort `files` so that those matching earlier globs in `priority` come
+/// first, with ties broken by path
+pub fn order_by_priority(files: &[PathBuf], options: &Options, priority: &[GlobList]) -> Vec<PathBuf> {
+    let rank = |path: &Path| priority.iter()
+        .position(|globs| globs.is_match(options.relative_path(path)))
+        .unwrap_or(priority.len());
+
+    let mut ordered = files.to_vec();
+    ordered.sort_by_cached_key(|path| (rank(path), path.clone()));
 
-    return Ok(file_paths);
+    return ordered;
 }
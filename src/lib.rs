@@ -0,0 +1,43 @@
+//! Finds duplicate lines, blocks and runs of tokens in text files, and can
+//! remove duplicate lines. The `strainer` binary is a command-line front end
+//! to `scanner::Scanner`, which can be used directly to get the results as a
+//! `scanner::ScanReport` instead of text.
+
+#![allow(clippy::needless_return)]
+
+extern crate crossbeam;
+extern crate ignore;
+extern crate globset;
+extern crate regex;
+extern crate unicode_normalization;
+extern crate similar;
+extern crate tempfile;
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
+
+#[cfg(feature = "syntax-highlighting")]
+extern crate syntect;
+
+pub mod baseline;
+pub mod config;
+pub mod counting;
+pub mod encoding;
+pub mod error;
+pub mod normalize;
+pub mod options;
+pub mod pattern;
+pub mod printing;
+pub mod report;
+pub mod scanner;
+pub mod suppress;
+
+mod blocks;
+mod comments;
+mod gather_paths;
+mod repeats;
+mod rewrite;
+mod tokens;
+
+pub use error::{FileError, ScanError};
+pub use scanner::{Duplicate, ScanReport, Scanner};
@@ -1,312 +1,799 @@
 #![allow(dead_code)]
+#![allow(clippy::needless_return)]
 
-use std::collections::HashSet;
-
-#[cfg(feature = "syntax-highlighting")]
-use std::{collections::HashMap, ffi::OsStr};
-use std::io::{self, prelude::*};
-use std::{fs::File, path::{PathBuf, Path}, time::SystemTime, sync::Arc};
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::{PathBuf, Path};
 
 extern crate clap;
-extern crate crossbeam;
-
-#[cfg(feature = "syntax-highlighting")]
-extern crate syntect;
-
-mod options;
-mod gather_paths;
-mod counting;
-mod pattern;
-mod printing;
-mod blocks;
+extern crate regex;
+extern crate strainer;
+extern crate toml;
 
-use blocks::count_chunks;
 use clap::ArgMatches;
-use gather_paths::list_files_in_dir;
-use counting::{Occurrences, count_lines, merge_records, strip_lines};
-use options::Mode;
-use pattern::parse_pattern;
-
-use crate::counting::FileLocation;
-use crate::options::{Options, SearchResult};
-
-#[cfg(feature = "syntax-highlighting")]
-use syntect::{easy::HighlightLines, highlighting::ThemeSet, parsing::SyntaxSet};
-
-#[cfg(feature = "syntax-highlighting")]
-use crate::printing::Highlighter;
+use regex::Regex;
+use strainer::{ScanReport, Scanner};
+use strainer::config::{CONFIG_FILE_NAME, Config, ConfigOverride, OPTION_ALIASES, settings_to_args};
+use strainer::counting::strip_lines_streaming;
+use strainer::encoding::Encoding;
+use strainer::normalize::{NormalizeStep, Normalizer};
+use strainer::options::{CommentFilter, DryRun, DuplicateUnit, Keep, Mode, NormalizerOverride, Options, OutputFormat, Thresholds, WalkOptions};
+use strainer::pattern::{GlobList, parse_pattern, validate_glob, validate_regex};
+use strainer::report::{DuplicateGroup, FailedFile, Report, SCHEMA_VERSION, Summary, threshold_violations, write_report};
 
 #[cfg(not(feature = "syntax-highlighting"))]
-use crate::printing::print_all_unhighlighted;
+use strainer::printing::print_all_unhighlighted;
 #[cfg(feature = "syntax-highlighting")]
-use crate::printing::print_occurences_highlighted;
+use strainer::printing::print_all_highlighted;
+
+/// Passed as DIRECTORY to filter standard input instead of searching files
+const STDIN_DIRECTORY: &str = "-";
+
+/// Exit status when duplication breaks one of the `Thresholds`, or `--check`
+/// finds lines that would be removed
+const EXIT_VIOLATIONS: i32 = 1;
+
+/// Exit status when something went wrong (including invalid arguments)
+const EXIT_ERROR: i32 = 2;
+
+/// Options that can only be used along with another one
+const REQUIREMENTS: &[(&str, &str)] = &[
+    ("parameterize", "tokens"),
+    ("consistent_renaming", "parameterize"),
+    ("priority", "remove_duplicates"),
+    ("keep", "remove_duplicates"),
+    ("backup_suffix", "remove_duplicates"),
+    ("dry_run", "remove_duplicates"),
+    ("count_only", "dry_run"),
+    ("check", "remove_duplicates"),
+];
+
+/// Pairs of options that can't be used together
+const CONFLICTS: &[(&str, &str)] = &[
+    ("tokens", "blocks"),
+    ("tokens", "remove_duplicates"),
+    ("priority", "same_file"),
+    ("baseline", "remove_duplicates"),
+    ("write_baseline", "remove_duplicates"),
+    ("strip_comments", "only_comments"),
+];
+
+/// Stream standard input to standard output with duplicate lines removed.
+/// Only the options that make sense for a single stream of lines are
+/// allowed.
+fn filter_stdin(matches: &ArgMatches, options: &Options) {
+    let unsupported = [
+        "same_file", "blocks", "tokens", "remove_duplicates", "strip_comments", "only_comments", "path_pattern", "exclude",
+        "format", "fail_on_duplicates", "max_duplicate_lines", "max_duplication_percent", "baseline", "write_baseline",
+        "strict", "encoding", "dry_run", "check", "backup_suffix",
+    ];
+
+    if let Some(arg) = unsupported.iter().find(|arg| matches.is_present(arg)) {
+        exit_with_usage_error(clap::Error::with_description(
+            &format!("--{} can't be used when reading from standard input", arg),
+            clap::ErrorKind::ArgumentConflict,
+        ));
+    }
 
-const MAX_THREADS: usize = 10;
+    let stdin = io::stdin();
+    let stdout = io::stdout();
 
-fn mode_from_matches(matches: &ArgMatches) -> Result<Mode, &'static str> {
+    match strip_lines_streaming(stdin.lock(), io::BufWriter::new(stdout.lock()), options) {
+        Ok(_) => {},
+        // Whatever we were writing to stopped reading (eg. `| head`)
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {},
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(EXIT_ERROR);
+        },
+    }
+}
+
+fn mode_from_matches(matches: &ArgMatches) -> Mode {
     if matches.is_present("remove_duplicates") {
         if !matches.is_present("same_file") {
-            Err("ERROR: Can't use --remove_duplicates without --same_file")
+            Mode::RemoveDuplicatesAcrossFiles
         } else {
-            Ok(Mode::RemoveDuplicates)
+            Mode::RemoveDuplicates
         }
     } else {
         if !matches.is_present("same_file") {
-            Ok(Mode::AllFiles)
+            Mode::AllFiles
         } else {
-            Ok(Mode::SameFile)
+            Mode::SameFile
         }
     }
 }
 
-fn main() {
-    let matches = clap::App::new("Strainer")
+fn validate_char(value: String) -> Result<(), String> {
+    match value.chars().count() {
+        1 => Ok(()),
+        _ => Err(format!("'{}' is not a single character", value)),
+    }
+}
+
+fn validate_non_negative_integer(value: String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(_) => Ok(()),
+        _ => Err(format!("'{}' is not a non-negative integer", value)),
+    }
+}
+
+fn validate_percentage(value: String) -> Result<(), String> {
+    match value.parse::<f64>() {
+        Ok(n) if (0.0..=100.0).contains(&n) => Ok(()),
+        _ => Err(format!("'{}' is not a percentage between 0 and 100", value)),
+    }
+}
+
+/// Parse `cli_args` with the options from a config file at `config_path`
+/// added. Options the command line already has, or that conflict with one
+/// it has, are left out, and so are options that require one that ends up
+/// not being used.
+fn matches_with_config(
+    cli_args: &[OsString],
+    settings: &toml::Table,
+    config_path: &Path,
+    given_on_cli: impl Fn(&str) -> bool,
+) -> ArgMatches<'static> {
+    let conflicts_with_cli = |name: &str| CONFLICTS.iter()
+        .any(|&(a, b)| (a == name && given_on_cli(b)) || (b == name && given_on_cli(a)));
+
+    // A flag set to false isn't used at all
+    let mut used = settings.iter()
+        .filter(|(name, value)| value.as_bool() != Some(false) && !given_on_cli(name) && !conflicts_with_cli(name))
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<&str>>();
+
+    // Dropping an option can leave others without what they require, so
+    // keep going until nothing changes
+    loop {
+        let is_active = |name: &str| given_on_cli(name) || used.contains(&name);
+        let unmet = used.iter().copied()
+            .filter(|&name| REQUIREMENTS.iter().any(|&(option, required)| option == name && !is_active(required)))
+            .collect::<Vec<&str>>();
+
+        if unmet.is_empty() {
+            break;
+        }
+
+        used.retain(|name| !unmet.contains(name));
+    }
+
+    let config_args = settings_to_args(settings, |name| !used.contains(&name))
+        .unwrap_or_else(|e| exit_with_error(&format!("In {}: {}", config_path.display(), e)));
+
+    // The command line has already been parsed on its own, so any error here
+    // comes from the config file
+    app()
+        .setting(clap::AppSettings::ColorNever)
+        .get_matches_from_safe(cli_args.iter().cloned().chain(config_args.into_iter().map(OsString::from)))
+        .unwrap_or_else(|e| {
+            // Leave out the usage that follows the error itself
+            let message = e.message.lines()
+                .take_while(|line| !line.starts_with("USAGE:"))
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<&str>>()
+                .join(" ");
+
+            exit_with_error(&format!("In {}: {}", config_path.display(), message.trim_start_matches("error: ")))
+        })
+}
+
+/// The normalizer for the files an `[[override]]` section applies to
+fn normalizer_override(
+    config: &Config,
+    config_override: &ConfigOverride,
+    matches: &ArgMatches,
+    given_on_cli: impl Fn(&str) -> bool,
+) -> NormalizerOverride {
+    let in_config = |e: String| -> ! { exit_with_error(&format!("In {}: {}", config.path.display(), e)) };
+
+    // Settings in the override replace the ones at the top level of the
+    // config file, which are already in `matches`
+    let values_of = |name: &str| match config_override.settings.contains_key(name) && !given_on_cli(name) {
+        true => config_override.values_of(name),
+        false => values_from_matches(matches, name),
+    };
+
+    NormalizerOverride {
+        paths:      GlobList::new(config_override.paths.iter().map(String::as_str))
+                        .unwrap_or_else(|e| in_config(e.to_string())),
+        normalizer: normalizer_from_values(values_of)
+                        .unwrap_or_else(|e| in_config(format!("Invalid regex in 'replace': {}", e))),
+    }
+}
+
+/// Print an error and exit with `EXIT_ERROR`
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("ERROR: {}", message);
+    std::process::exit(EXIT_ERROR);
+}
+
+/// Print a usage error and exit. clap's own `Error::exit` uses status 1,
+/// which is reserved for `EXIT_VIOLATIONS`.
+fn exit_with_usage_error(error: clap::Error) -> ! {
+    if !error.use_stderr() {
+        // --help and --version
+        error.exit();
+    }
+
+    eprintln!("{}", error.message);
+    std::process::exit(EXIT_ERROR);
+}
+
+fn validate_positive_integer(value: String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(()),
+        _ => Err(format!("'{}' is not a positive integer", value)),
+    }
+}
+
+/// Regexes have already been checked by `validate_regex`
+fn regexes_from_matches(matches: &ArgMatches, name: &str) -> Vec<Regex> {
+    matches.values_of(name)
+        .map(|iter| iter.map(|s| Regex::new(s).unwrap()).collect())
+        .unwrap_or_default()
+}
+
+fn normalizer_from_matches(matches: &ArgMatches) -> Normalizer {
+    normalizer_from_values(|name| values_from_matches(matches, name))
+        .unwrap_or_else(|e| exit_with_usage_error(clap::Error::with_description(
+            &format!("Invalid value for '--replace <REGEX> <REPLACEMENT>': {}", e),
+            clap::ErrorKind::ValueValidation,
+        )))
+}
+
+/// The values given for option `name`, or none for a flag, if it was given
+fn values_from_matches(matches: &ArgMatches, name: &str) -> Option<Vec<String>> {
+    if !matches.is_present(name) {
+        return None;
+    }
+
+    return Some(matches.values_of(name).map(|values| values.map(String::from).collect()).unwrap_or_default());
+}
+
+/// Build a normalizer from the normalization options, whose values are
+/// looked up with `values_of` (see `values_from_matches`)
+fn normalizer_from_values(values_of: impl Fn(&str) -> Option<Vec<String>>) -> Result<Normalizer, regex::Error> {
+    let mut steps = Vec::new();
+
+    match values_of("unicode_form").as_deref() {
+        Some([form]) if form == "nfc" => steps.push(NormalizeStep::Nfc),
+        Some([form]) if form == "nfkc" => steps.push(NormalizeStep::Nfkc),
+        _ => {},
+    }
+
+    if let Some(markers) = values_of("strip_trailing_comment") {
+        steps.push(NormalizeStep::StripTrailingComment(markers));
+    }
+
+    if let Some(values) = values_of("replace") {
+        for pair in values.chunks_exact(2) {
+            steps.push(NormalizeStep::Replace(Regex::new(&pair[0])?, pair[1].clone()));
+        }
+    }
+
+    if let Some(chars) = values_of("squash_chars") {
+        steps.push(NormalizeStep::SquashChars(chars.iter().filter_map(|s| s.chars().next()).collect()));
+    }
+
+    if values_of("collapse_whitespace").is_some() {
+        steps.push(NormalizeStep::CollapseWhitespace);
+    }
+
+    if values_of("trim_whitespace").is_some() {
+        steps.push(NormalizeStep::Trim);
+    }
+
+    if values_of("ignore_case").is_some() {
+        steps.push(NormalizeStep::CaseFold);
+    }
+
+    Ok(Normalizer::new(steps))
+}
+
+/// A command-line option, with the aliases, requirements and conflicts it
+/// has in `OPTION_ALIASES`, `REQUIREMENTS` and `CONFLICTS`
+fn arg(name: &'static str) -> clap::Arg<'static, 'static> {
+    let mut arg = clap::Arg::with_name(name);
+
+    for &(alias, _) in OPTION_ALIASES.iter().filter(|&&(_, option)| option == name) {
+        arg = arg.visible_alias(alias);
+    }
+
+    for &(_, required) in REQUIREMENTS.iter().filter(|&&(option, _)| option == name) {
+        arg = arg.requires(required);
+    }
+
+    for &(_, other) in CONFLICTS.iter().filter(|&&(option, _)| option == name) {
+        arg = arg.conflicts_with(other);
+    }
+
+    return arg;
+}
+
+fn app() -> clap::App<'static, 'static> {
+    clap::App::new("Strainer")
         .version("0.1")
         .author("Brandon Smith <mail@brandonsmith.ninja>")
         .about("Find duplicate lines in text files")
-        .arg(clap::Arg::with_name("DIRECTORY")
-            .help("The root directory to search within")
+        .arg(arg("DIRECTORY")
+            .help("The root directory to search within, or '-' to read standard input and write it to standard output with duplicate lines removed")
             .required(true)
             .display_order(0))
-        .arg(clap::Arg::with_name("path_pattern")
+        .arg(arg("path_pattern")
             .short("p")
             .long("path_pattern")
-            .value_name("PAT")
-            .help("A basic pattern string to filter which files will be searched. Asterisks ('*') will match any substring.")
-            .default_value("*")
-            .takes_value(true))
-        .arg(clap::Arg::with_name("line_delimiter")
+            .value_name("GLOB")
+            .help("A glob to filter which files will be searched (supports '*', '**', '?', '[abc]' and '{a,b}'). Globs are matched against the whole path relative to DIRECTORY, so '*.rs' only matches files directly in it and '**/*.rs' matches them anywhere; '*' doesn't match '/'. A glob ending in '/' matches directories and everything in them. Can be given multiple times. [default: all files]")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_glob))
+        .arg(arg("exclude")
+            .short("x")
+            .long("exclude")
+            .value_name("GLOB")
+            .help("A glob for files and directories to skip, with the same syntax as --path_pattern. Excluded directories are not searched at all. Can be given multiple times.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_glob))
+        .arg(arg("encoding")
+            .long("encoding")
+            .value_name("ENCODING")
+            .help("How to read files that don't start with a byte order mark (files that do are read as the UTF-8 or UTF-16 it indicates). 'bytes' reads UTF-8 where it's valid and compares any other bytes as they are, printing them as \\xNN escapes. Files that aren't valid in the encoding are skipped and listed at the end. Files with a NUL byte near the start are skipped as binaries, unless they're UTF-16. Defaults to utf-8.")
+            .takes_value(true)
+            .possible_values(&["utf-8", "utf-16le", "utf-16be", "latin1", "bytes"]))
+        .arg(arg("line_delimiter")
             .short("d")
             .long("line_delimiter")
             .value_name("CHAR")
             .help("The character that delimits 'lines'. Can be used, for example, to search a natural-language file by passing '.' to split on sentences. [default: \\n]")
-            .takes_value(true))
-        .arg(clap::Arg::with_name("line_pattern")
-            .short("lp")
+            .takes_value(true)
+            .validator(validate_char))
+        .arg(arg("line_pattern")
+            .short("l")
             .long("line_pattern")
             .value_name("PAT")
             .help("A basic pattern string to filter which lines will show up in results. Asterisks ('*') will match any substring.")
             .default_value("*")
             .takes_value(true))
-        .arg(clap::Arg::with_name("trim_whitespace")
+        .arg(arg("line_regex")
+            .long("line_regex")
+            .value_name("REGEX")
+            .help("A regular expression to filter which lines will show up in results. Can be given multiple times, in which case a line must match at least one of them.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_regex))
+        .arg(arg("exclude_line_regex")
+            .long("exclude_line_regex")
+            .value_name("REGEX")
+            .help("A regular expression for lines that should be left out of results. Can be given multiple times, in which case a line matching any of them is left out.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_regex))
+        .arg(arg("trim_whitespace")
             .short("t")
             .long("trim_whitespace")
             .help("Trim whitespace from the start and end of each line before comparing."))
-        .arg(clap::Arg::with_name("same_file")
-            .short("sf")
+        .arg(arg("collapse_whitespace")
+            .long("collapse_whitespace")
+            .help("Treat any continuous sequence of whitespace inside a line as a single space before comparing."))
+        .arg(arg("ignore_case")
+            .long("ignore_case")
+            .help("Compare lines case-insensitively."))
+        .arg(arg("unicode_form")
+            .long("unicode_form")
+            .value_name("FORM")
+            .help("Unicode-normalize each line before comparing, so that equivalent sequences of code points compare equal.")
+            .possible_values(&["nfc", "nfkc"])
+            .takes_value(true))
+        .arg(arg("strip_trailing_comment")
+            .long("strip_trailing_comment")
+            .value_name("MARKER")
+            .help("Ignore everything from this marker (for example '//' or '#') to the end of the line before comparing. Can be given multiple times.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(arg("replace")
+            .long("replace")
+            .value_names(&["REGEX", "REPLACEMENT"])
+            .help("Replace every match of REGEX in each line with REPLACEMENT (which may refer to capture groups like '$1') before comparing. Can be given multiple times; replacements are applied in order.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(2))
+        .arg(arg("same_file")
+            .short("s")
             .long("same_file")
             .help("Only check for duplicate lines within the same file."))
-        .arg(clap::Arg::with_name("blocks")
+        .arg(arg("blocks")
             .short("b")
             .long("blocks")
-            .help("Check for entire blocks of duplicate lines."))
-        .arg(clap::Arg::with_name("remove_duplicates")
-            .short("rm")
+            .help("Check for entire blocks of duplicate lines. Only the largest duplicated blocks are reported, as ranges of lines."))
+        .arg(arg("min_block_lines")
+            .long("min_block_lines")
+            .value_name("N")
+            .help("The minimum number of (non-empty) lines a duplicated block must have to be reported in --blocks mode.")
+            .default_value("2")
+            .validator(validate_positive_integer)
+            .takes_value(true))
+        .arg(arg("max_block_lines")
+            .long("max_block_lines")
+            .value_name("N")
+            .help("The maximum number of (non-empty) lines a duplicated block can have to be reported in --blocks mode. Longer blocks are left out.")
+            .validator(validate_positive_integer)
+            .takes_value(true))
+        .arg(arg("tokens")
+            .long("tokens")
+            .help("Check for duplicated runs of tokens, which finds copied code even if it has been reformatted. Only files in recognized languages (based on their extension) are searched."))
+        .arg(arg("min_tokens")
+            .long("min_tokens")
+            .value_name("N")
+            .help("The minimum number of tokens a duplicated run must have to be reported in --tokens mode.")
+            .default_value("100")
+            .validator(validate_positive_integer)
+            .takes_value(true))
+        .arg(arg("parameterize")
+            .long("parameterize")
+            .help("In --tokens mode, treat all identifiers as the same token, and all number, string and character literals as the same token. This finds copied code whose variables have been renamed or whose constants have been changed."))
+        .arg(arg("consistent_renaming")
+            .long("consistent_renaming")
+            .help("With --parameterize, only treat code as duplicated if identifiers and literals were renamed consistently (every 'a' in one copy is the same name in the other). This is checked for the longest duplicated runs only, so a shorter run that was renamed consistently inside a longer one that wasn't isn't reported."))
+        .arg(arg("remove_duplicates")
+            .short("r")
             .long("remove_duplicates")
-            .help("Remove duplicate lines (keep the first occurrence). Requires --same_file. DANGER: Overwrites source files, use with caution!"))
-        .arg(clap::Arg::with_name("squash_chars")
-            .short("s")
+            .help("Remove duplicate lines, keeping one occurrence of each (see --keep). Without --same_file, a line found in several files is kept in only one of them (see --priority). DANGER: Overwrites source files, use with caution! Try --dry_run first."))
+        .arg(arg("priority")
+            .long("priority")
+            .value_name("GLOB")
+            .help("With --remove_duplicates across files, the order of files that --keep refers to: files matching the first glob come first, then files matching the second, and so on, then all other files. Files are otherwise ordered by path. Can be given multiple times.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_glob))
+        .arg(arg("keep")
+            .long("keep")
+            .value_name("POLICY")
+            .help("With --remove_duplicates, which occurrence of a duplicated line to keep: the first, the last, or the first occurrence of the line's most common raw form (useful when normalization makes differently written lines compare equal). Defaults to first.")
+            .takes_value(true)
+            .possible_values(&["first", "last", "most_common"]))
+        .arg(arg("backup_suffix")
+            .long("backup_suffix")
+            .value_name("SUFFIX")
+            .help("With --remove_duplicates, keep a copy of each file's previous version alongside it, named with SUFFIX appended (for example '.orig').")
+            .takes_value(true))
+        .arg(arg("dry_run")
+            .long("dry_run")
+            .help("With --remove_duplicates, print a unified diff of what would be removed from each file instead of changing any files."))
+        .arg(arg("count_only")
+            .long("count_only")
+            .help("With --dry_run, print the number of lines that would be removed from each file instead of a diff."))
+        .arg(arg("check")
+            .long("check")
+            .help("With --remove_duplicates, don't change any files, but exit with a non-zero status if any file has duplicate lines that would be removed. Implies --dry_run --count_only unless --dry_run is given."))
+        .arg(arg("fail_on_duplicates")
+            .long("fail_on_duplicates")
+            .help("Exit with status 1 if any duplicates are found. Errors exit with status 2."))
+        .arg(arg("max_duplicate_lines")
+            .long("max_duplicate_lines")
+            .value_name("N")
+            .help("Exit with status 1 if more than N lines are duplicates. A line counts as a duplicate if it's a copy of one found earlier, so the first occurrence of each duplicate doesn't count. In remove mode, the lines removed are counted.")
+            .takes_value(true)
+            .validator(validate_non_negative_integer))
+        .arg(arg("max_duplication_percent")
+            .long("max_duplication_percent")
+            .value_name("P")
+            .help("Exit with status 1 if more than P percent of all lines searched are duplicates (counted as for --max_duplicate_lines).")
+            .takes_value(true)
+            .validator(validate_percentage))
+        .arg(arg("strict")
+            .long("strict")
+            .help("Exit with status 2 if any file or directory couldn't be searched (eg. because of its permissions, or because it isn't valid in the encoding it's read as). Otherwise they're skipped and listed at the end."))
+        .arg(arg("baseline")
+            .long("baseline")
+            .value_name("FILE")
+            .help("Only report duplicates that aren't recorded in FILE (written by --write_baseline), or that have more occurrences than were recorded. Duplicates are identified by their normalized text, so they stay recorded when lines move. The summary and limits such as --max_duplicate_lines only count what's reported.")
+            .takes_value(true))
+        .arg(arg("write_baseline")
+            .long("write_baseline")
+            .value_name("FILE")
+            .help("Record every duplicate found in FILE, to be passed to --baseline in later runs.")
+            .takes_value(true))
+        .arg(arg("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help("How to print results. 'json' prints a single JSON document and 'ndjson' prints one JSON object per line (a header, each duplicate group or changed file, then a summary). Both include a schema_version, and timings are included in the summary instead of being printed separately. 'sarif' prints a SARIF 2.1.0 log for code scanning tools, with one result per duplicate group. Defaults to text.")
+            .takes_value(true)
+            .possible_values(&["text", "json", "ndjson", "sarif"]))
+        .arg(arg("squash_chars")
+            .short("q")
             .long("squash_chars")
             .help("Characters that should be 'squashed' when processing a line. When a character is 'squashed', any continuous sequence of that character will be treated as a single instance. This cen be used to, for example, normalize indentation.")
-            .default_value("false")
+            .takes_value(true)
             .multiple(true))
-        .get_matches();
+        .arg(arg("strip_comments")
+            .long("strip_comments")
+            .help("Remove comments before comparing lines, using the comment syntax for each file's language (based on its extension). Files in unrecognized languages are compared as-is."))
+        .arg(arg("only_comments")
+            .long("only_comments")
+            .help("Only compare the text of comments, using the comment syntax for each file's language (based on its extension). Can be used to find copied documentation. Files in unrecognized languages are skipped."))
+        .arg(arg("hidden")
+            .long("hidden")
+            .help("Search hidden files and directories (those whose names start with '.'), which are skipped by default."))
+        .arg(arg("no_gitignore")
+            .long("no_gitignore")
+            .help("Don't respect .gitignore files (including the global gitignore) when walking directories."))
+        .arg(arg("no_ignore_files")
+            .long("no_ignore_files")
+            .help("Don't respect .ignore files when walking directories."))
+        .arg(arg("no_git_exclude")
+            .long("no_git_exclude")
+            .help("Don't respect .git/info/exclude when walking directories."))
+        .arg(arg("threads")
+            .short("j")
+            .long("threads")
+            .value_name("N")
+            .help("How many files to search at once. Defaults to the number of CPUs.")
+            .takes_value(true)
+            .validator(validate_positive_integer))
+        .arg(arg("config")
+            .long("config")
+            .value_name("FILE")
+            .help("Read options from FILE instead of DIRECTORY/.strainer.toml. Options given on the command line take precedence over the ones in the file.")
+            .takes_value(true))
+}
 
-    let directory = matches.value_of("DIRECTORY").unwrap();
-    let path_pattern = matches.value_of("path_pattern").unwrap();
-    let mode = match mode_from_matches(&matches) {
-        Ok(mode) => mode,
-        Err(e) => panic!("{}", e),
+fn main() {
+    let cli_args = std::env::args_os().collect::<Vec<OsString>>();
+    let cli_matches = app().get_matches_from_safe(&cli_args)
+        .unwrap_or_else(|e| exit_with_usage_error(e));
+
+    let config_path = match cli_matches.value_of("config") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(Path::new(cli_matches.value_of("DIRECTORY").unwrap()).join(CONFIG_FILE_NAME))
+            .filter(|path| path.is_file()),
     };
+    let config = config_path.map(|path| {
+        Config::read(&path, Path::new(cli_matches.value_of("DIRECTORY").unwrap())).unwrap_or_else(|e| exit_with_error(&e))
+    });
+
+    // Options from the config file are added after the ones on the command
+    // line, unless the command line already has them
+    let given_on_cli = |name: &str| cli_matches.occurrences_of(name) > 0;
+    let matches = match &config {
+        Some(config) => matches_with_config(&cli_args, &config.settings, &config.path, given_on_cli),
+        None => cli_matches.clone(),
+    };
+
+    let directory = matches.value_of("DIRECTORY").unwrap();
+    let priority = matches.values_of("priority").into_iter().flatten()
+        .map(|glob| GlobList::new(Some(glob)).unwrap())
+        .collect::<Vec<GlobList>>();
+    let mode = mode_from_matches(&matches);
 
     let options = Options {
+        encoding:           matches.value_of("encoding").and_then(Encoding::from_name).unwrap_or(Encoding::Utf8),
         line_delimiter:     matches.value_of("line_delimiter").map(|s| s.chars().next().unwrap()).unwrap_or('\n'),
         line_pattern:       parse_pattern(matches.value_of("line_pattern").unwrap()),
-        trim_whitespace:    matches.is_present("trim_whitespace"),
+        line_regexes:       regexes_from_matches(&matches, "line_regex"),
+        exclude_line_regexes: regexes_from_matches(&matches, "exclude_line_regex"),
+        normalizer:         normalizer_from_matches(&matches),
+        normalizer_overrides: config.iter()
+                                .flat_map(|config| config.overrides.iter().map(move |o| (config, o)))
+                                .map(|(config, o)| normalizer_override(config, o, &matches, given_on_cli))
+                                .collect(),
+        roots:              vec![PathBuf::from(directory)],
+        comments:           if matches.is_present("strip_comments") {
+                                CommentFilter::Strip
+                            } else if matches.is_present("only_comments") {
+                                CommentFilter::Only
+                            } else {
+                                CommentFilter::Keep
+                            },
         mode,
-        squash_chars:       matches.values_of("squash_chars")
-                            .map(|iter| 
-                                iter.map(|s| s.chars().next().unwrap()).collect())
-                            .unwrap_or(vec![]),
         blocks:             matches.is_present("blocks"),
+        min_block_lines:    matches.value_of("min_block_lines").unwrap().parse().unwrap(),
+        max_block_lines:    matches.value_of("max_block_lines").map(|n| n.parse().unwrap()),
+        tokens:             matches.is_present("tokens"),
+        min_tokens:         matches.value_of("min_tokens").unwrap().parse().unwrap(),
+        parameterize:       matches.is_present("parameterize"),
+        consistent_renaming: matches.is_present("consistent_renaming"),
         ignore_delimiters:  vec![], // TOTO: Implement
+        dry_run:            if matches.is_present("dry_run") && !matches.is_present("count_only") {
+                                Some(DryRun::Diff)
+                            } else if matches.is_present("dry_run") || matches.is_present("check") {
+                                Some(DryRun::Count)
+                            } else {
+                                None
+                            },
+        keep:               match matches.value_of("keep") {
+                                Some("last") => Keep::Last,
+                                Some("most_common") => Keep::MostCommon,
+                                _ => Keep::First,
+                            },
+        backup_suffix:      matches.value_of("backup_suffix").map(String::from),
+        baseline:           matches.value_of("baseline").map(PathBuf::from),
+        write_baseline:     matches.value_of("write_baseline").map(PathBuf::from),
+    };
+    let check = matches.is_present("check");
+    let strict = matches.is_present("strict");
+    let thresholds = Thresholds {
+        fail_on_duplicates:         matches.is_present("fail_on_duplicates"),
+        max_duplicate_lines:        matches.value_of("max_duplicate_lines").map(|n| n.parse().unwrap()),
+        max_duplication_percent:    matches.value_of("max_duplication_percent").map(|p| p.parse().unwrap()),
+    };
+    if matches.value_of("format") == Some("sarif") && matches.is_present("remove_duplicates") {
+        exit_with_usage_error(clap::Error::with_description(
+            "--format sarif can't be used with --remove_duplicates",
+            clap::ErrorKind::ArgumentConflict,
+        ));
+    }
+    let format = match matches.value_of("format") {
+        Some("json") => OutputFormat::Json,
+        Some("ndjson") => OutputFormat::Ndjson,
+        Some("sarif") => OutputFormat::Sarif,
+        _ => OutputFormat::Text,
     };
 
+    if directory == STDIN_DIRECTORY {
+        filter_stdin(&matches, &options);
+        return;
+    }
 
-    // Enumerate files
-
-    println!("Searching...");
-    let start_listing = SystemTime::now();
-    let files = list_files_in_dir(
-        Path::new(&directory), 
-        &parse_pattern(path_pattern)
-    ).unwrap();
-
-    let files_ref = &files;
-    let options_ref = &options;
-    let end_walk = SystemTime::now();
-
-    let results = SearchResult::from_mode(options_ref.mode);
-    let results_arc = Arc::new(&results);
-
-
-    // Search for duplicate lines
-
-    let start_processing = SystemTime::now();
-    let files_count = files.len();
-    let files_per_thread = std::cmp::max(files_count / MAX_THREADS, 1);
-    crossbeam::scope(move |scope| {
-        for chunk in files_ref.chunks(files_per_thread) {
-            let local_results_arc = results_arc.clone();
-
-            scope.spawn(move |_| {
-                for file_path in chunk {
-                    match local_results_arc.as_ref() {
-                        SearchResult::RemoveDuplicates => {
-                            dedupe_file(options_ref, &file_path).unwrap();
-                        },
-                        SearchResult::SameFile(results) => {
-                            if let Ok(file_results) = search_file(options_ref, &file_path) {
-                                results.lock().unwrap().push(file_results);
-                            }
-                        },
-                        SearchResult::AllFiles(results) => {
-                            if let Ok(file_results) = search_file(options_ref, &file_path) {
-                                merge_records(&mut results.lock().unwrap(), file_results);
-                            }
-                        },
-                    }
-                }
-            });
-        }
-    }).unwrap();
-    let end_search = SystemTime::now();
+    let walk_options = WalkOptions {
+        hidden:             matches.is_present("hidden"),
+        git_ignore:         !matches.is_present("no_gitignore"),
+        ignore_files:       !matches.is_present("no_ignore_files"),
+        git_exclude:        !matches.is_present("no_git_exclude"),
+    };
+    let mut scanner = Scanner::new()
+        .options(options)
+        .include(GlobList::new(matches.values_of("path_pattern").into_iter().flatten()).unwrap())
+        .exclude(GlobList::new(matches.values_of("exclude").into_iter().flatten()).unwrap())
+        .walk_options(walk_options)
+        .priority(priority);
+    if let Some(threads) = matches.value_of("threads") {
+        scanner = scanner.threads(threads.parse().unwrap());
+    }
 
 
-    // Print output
+    // Search for duplicates
 
-    match results {
-        SearchResult::RemoveDuplicates => {
-            println!("Searched {} files and removed any duplicate lines", files_count);
-        },
-        SearchResult::SameFile(results) => {
-            let results_lock = results.lock().unwrap();
+    if format == OutputFormat::Text {
+        // Not worth failing over if standard output has already gone away
+        let _ = writeln!(io::stdout(), "Searching...");
+    }
+    let scan_report = scanner.scan().unwrap_or_else(|e| exit_with_error(&e.to_string()));
+    let would_change = check && !scan_report.changes.is_empty();
 
-            let mut duplicates = results_lock.iter()
-                .map(|one_file| 
-                    one_file.iter().filter(|entry| entry.1.len() > 1))
-                .flatten()
-                .collect::<Vec<(&String, &Vec<FileLocation>)>>();
 
-            duplicates.sort();
-            
-            #[cfg(feature = "syntax-highlighting")]
-            let (output_buffer, duplicate_count) = print_all_highlighted(duplicates.into_iter());
+    // Print output
 
-            #[cfg(not(feature = "syntax-highlighting"))]
-            let (output_buffer, duplicate_count) = print_all_unhighlighted(duplicates.into_iter());
-
-            let files_with_duplicates = {
-                let mut files_set = HashSet::new();
-                for file_results in results_lock.iter() {
-                    for dupe in file_results.iter().filter(|entry| entry.1.len() > 1) {
-                        for fl in dupe.1 {
-                            files_set.insert(&fl.path);
-                        }
-                    }
-                }
+    let summary = if format == OutputFormat::Text {
+        print_text(&scan_report)
+    } else {
+        let report = Report {
+            schema_version: SCHEMA_VERSION,
+            unit:           scan_report.unit,
+            groups:         scan_report.duplicates.iter()
+                                .map(|duplicate| DuplicateGroup::new(duplicate.fingerprint.clone(), &duplicate.text, &duplicate.locations))
+                                .collect(),
+            summary:        scan_report.summary(),
+            changes:        scan_report.changes,
+            errors:         scan_report.errors.iter().map(FailedFile::from).collect(),
+        };
+
+        print_report(&report, format, Path::new(&directory));
+
+        report.summary
+    };
 
-                files_set.len()
-            };
+    // Files that couldn't be searched are listed last, so they don't get
+    // lost in the output
+    for error in &scan_report.errors {
+        eprintln!("ERROR: {}", error);
+    }
 
-            println!("{}", &output_buffer);
-            println!();
-            println!("Searched {} files", files_count);
-            println!("Found {} duplicated lines across {} of them", duplicate_count, files_with_duplicates);
-        },
-        SearchResult::AllFiles(results) => {
-            let results_lock = results.lock().unwrap();
-            let mut duplicates = results_lock.iter()
-                .filter(|entry| entry.1.len() > 1)
-                .collect::<Vec<(&String, &Vec<FileLocation>)>>();
-            
-            duplicates.sort();
+    if strict && !scan_report.errors.is_empty() {
+        std::process::exit(EXIT_ERROR);
+    }
 
-            #[cfg(feature = "syntax-highlighting")]
-            let (output_buffer, duplicate_count) = print_all_highlighted(duplicates.into_iter());
+    // Exit with a failure status if duplication broke any of the limits
+    let violations = threshold_violations(&summary, &thresholds);
+    for violation in &violations {
+        eprintln!("{}", violation);
+    }
 
-            #[cfg(not(feature = "syntax-highlighting"))]
-            let (output_buffer, duplicate_count) = print_all_unhighlighted(duplicates.into_iter());
+    if would_change || !violations.is_empty() {
+        std::process::exit(EXIT_VIOLATIONS);
+    }
+}
 
-            let files_with_duplicates = {
-                let duplicates = results_lock.iter().filter(|entry| entry.1.len() > 1);
+/// Print the changes or duplicates found, a summary of them and how long
+/// everything took. The summary is returned.
+fn print_text(scan_report: &ScanReport) -> Summary {
+    let summary = scan_report.summary();
+    let stdout = io::stdout();
 
-                let mut files_set = HashSet::new();
-                for dupe in duplicates {
-                    for fl in dupe.1 {
-                        files_set.insert(&fl.path);
-                    }
-                }
+    match write_text(scan_report, &summary, stdout.lock()) {
+        Ok(()) => {},
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {},
+        Err(e) => exit_with_error(&e.to_string()),
+    }
 
-                files_set.len()
-            };
+    return summary;
+}
 
-            println!("{}", &output_buffer);
-            println!();
-            println!("Searched {} files", files_count);
-            println!("Found {} duplicated lines across {} of them", duplicate_count, files_with_duplicates);
+fn write_text(scan_report: &ScanReport, summary: &Summary, mut out: impl Write) -> io::Result<()> {
+    match scan_report.mode {
+        Mode::RemoveDuplicates | Mode::RemoveDuplicatesAcrossFiles => {
+            for change in &scan_report.changes {
+                match &change.diff {
+                    Some(diff) => write!(out, "{}", diff)?,
+                    None => writeln!(out, "{}: {} duplicate lines", change.path.display(), change.removed_lines)?,
+                }
+            }
+
+            writeln!(out)?;
+            writeln!(out, "Searched {} files", summary.files_searched)?;
+            if summary.dry_run {
+                writeln!(out, "Would remove {} duplicate lines from {} of them", summary.removed_lines, summary.files_with_duplicates)?;
+            } else {
+                writeln!(out, "Removed {} duplicate lines from {} of them", summary.removed_lines, summary.files_with_duplicates)?;
+            }
         },
-    };
+        Mode::AllFiles | Mode::SameFile => {
+            let duplicates = scan_report.duplicates.iter()
+                .map(|duplicate| (&duplicate.text, &duplicate.locations));
 
-    println!(
-        "Determining file list took {:?}ms",
-        end_walk.duration_since(start_listing).unwrap().as_millis()
-    );
+            #[cfg(feature = "syntax-highlighting")]
+            let (output_buffer, duplicate_count) = print_all_highlighted(duplicates);
 
-    println!(
-        "Processing files took {:?}ms",
-        end_search.duration_since(start_processing).unwrap().as_millis()
-    );
-}
+            #[cfg(not(feature = "syntax-highlighting"))]
+            let (output_buffer, duplicate_count) = print_all_unhighlighted(duplicates);
 
+            writeln!(out, "{}", &output_buffer)?;
+            writeln!(out)?;
+            writeln!(out, "Searched {} files", summary.files_searched)?;
+            writeln!(out, "Found {} duplicated {} across {} of them", duplicate_count, duplicate_noun(scan_report.unit), summary.files_with_duplicates)?;
+            writeln!(out, "{} of {} lines are duplicates ({:.2}%)", summary.duplicate_lines, summary.total_lines, summary.duplication_percent())?;
+        },
+    }
 
-fn search_file(options: &Options, file_path: &PathBuf) -> Result<Occurrences, io::Error> {
-    let mut contents = String::new();
+    if summary.binary_files > 0 {
+        writeln!(out, "Skipped {} binary files", summary.binary_files)?;
+    }
 
-    let mut file = File::open(file_path)?;
-    file.read_to_string(&mut contents)?;
+    if summary.files_with_errors > 0 {
+        writeln!(out, "Couldn't search {} files or directories", summary.files_with_errors)?;
+    }
 
-    
-    let occurrences = if options.blocks {
-        count_chunks(
-            file_path,
-            &contents,
-            options,
-        )
-    } else {
-        count_lines(
-            file_path,
-            &contents,
-            options,
-        )
-    };
+    writeln!(out, "Determining file list took {:?}ms", summary.timings.listing_ms)?;
+    writeln!(out, "Searching took {:?}ms in total", summary.timings.total_ms)?;
 
-    return Ok(occurrences);
+    return out.flush();
 }
 
-fn dedupe_file(options: &Options, file_path: &PathBuf) -> Result<(), io::Error> {
-    let mut contents = String::new();
-
-    let mut file = File::open(file_path)?;
-    file.read_to_string(&mut contents)?;
+fn print_report(report: &Report, format: OutputFormat, root_dir: &Path) {
+    let stdout = io::stdout();
 
-    let new_contents = strip_lines(
-        &contents,
-        options,
-    );
-
-    let mut file = File::create(file_path)?;
-    file.write(new_contents.as_bytes())?;
+    match write_report(report, format, root_dir, stdout.lock()) {
+        Ok(()) => {},
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {},
+        Err(e) => exit_with_error(&e.to_string()),
+    }
+}
 
-    Ok(())
+/// What a single duplicate is called in the summary, given what it's made of
+fn duplicate_noun(unit: DuplicateUnit) -> &'static str {
+    match unit {
+        DuplicateUnit::Lines => "lines",
+        DuplicateUnit::Blocks => "blocks",
+        DuplicateUnit::Tokens => "runs of tokens",
+    }
 }
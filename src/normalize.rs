@@ -0,0 +1,209 @@
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+
+/// A single transformation applied to each line before it gets compared with
+/// other lines. Steps are always applied in the order they're declared in
+/// here, regardless of the order the options were given in.
+#[derive(Clone, Debug)]
+pub enum NormalizeStep {
+    /// Unicode Normalization Form C (canonical composition)
+    Nfc,
+    /// Unicode Normalization Form KC (compatibility composition)
+    Nfkc,
+    /// Cut off everything from the first occurrence of any of these markers
+    /// onward. This is purely textual; markers inside string literals will
+    /// also be cut.
+    StripTrailingComment(Vec<String>),
+    /// Replace every match of the regex with the replacement string, which
+    /// may refer to capture groups (`$1`, `${name}`)
+    Replace(Regex, String),
+    /// Any continuous sequence of one of these characters is treated as a
+    /// single instance
+    SquashChars(Vec<char>),
+    /// Any continuous sequence of whitespace is treated as a single space
+    CollapseWhitespace,
+    /// Remove whitespace from the start and end of the line
+    Trim,
+    /// Compare lines case-insensitively
+    CaseFold,
+}
+
+impl NormalizeStep {
+    /// Where this step comes in the order steps are applied in
+    fn rank(&self) -> usize {
+        match self {
+            NormalizeStep::Nfc => 0,
+            NormalizeStep::Nfkc => 1,
+            NormalizeStep::StripTrailingComment(_) => 2,
+            NormalizeStep::Replace(..) => 3,
+            NormalizeStep::SquashChars(_) => 4,
+            NormalizeStep::CollapseWhitespace => 5,
+            NormalizeStep::Trim => 6,
+            NormalizeStep::CaseFold => 7,
+        }
+    }
+
+    fn apply(&self, line: &str) -> String {
+        match self {
+            NormalizeStep::Nfc => line.nfc().collect(),
+            NormalizeStep::Nfkc => line.nfkc().collect(),
+            NormalizeStep::StripTrailingComment(markers) => {
+                let cut = markers.iter()
+                    .filter_map(|marker| line.find(marker.as_str()))
+                    .min()
+                    .unwrap_or(line.len());
+
+                line[..cut].to_owned()
+            },
+            NormalizeStep::Replace(regex, replacement) => regex.replace_all(line, replacement.as_str()).into_owned(),
+            NormalizeStep::SquashChars(chars) => {
+                let mut squashed = String::with_capacity(line.len());
+                let mut prev_char: Option<char> = None;
+
+                for c in line.chars() {
+                    let squashing = prev_char
+                        .map(|prev| prev == c && chars.contains(&prev))
+                        .unwrap_or(false);
+
+                    if !squashing {
+                        squashed.push(c);
+                    }
+
+                    prev_char = Some(c);
+                }
+
+                squashed
+            },
+            NormalizeStep::CollapseWhitespace => {
+                let mut collapsed = String::with_capacity(line.len());
+                let mut in_whitespace = false;
+
+                for c in line.chars() {
+                    if c.is_whitespace() {
+                        if !in_whitespace {
+                            collapsed.push(' ');
+                        }
+
+                        in_whitespace = true;
+                    } else {
+                        collapsed.push(c);
+                        in_whitespace = false;
+                    }
+                }
+
+                collapsed
+            },
+            NormalizeStep::Trim => line.trim().to_owned(),
+            NormalizeStep::CaseFold => line.to_lowercase(),
+        }
+    }
+}
+
+/// A pipeline of `NormalizeStep`s, applied in their fixed order. Every mode runs lines
+/// through the same `Normalizer` before comparing them, and the result is
+/// what gets used as the key for a line.
+#[derive(Clone, Debug, Default)]
+pub struct Normalizer {
+    steps: Vec<NormalizeStep>,
+}
+
+impl Normalizer {
+    /// Steps of the same kind (like several `Replace`s) keep the order
+    /// they're given in
+    pub fn new(mut steps: Vec<NormalizeStep>) -> Self {
+        steps.sort_by_key(NormalizeStep::rank);
+
+        Normalizer { steps }
+    }
+
+    pub fn steps(&self) -> &[NormalizeStep] {
+        &self.steps
+    }
+
+    pub fn normalize(&self, line: &str) -> String {
+        let mut line = line.to_owned();
+
+        for step in &self.steps {
+            line = step.apply(&line);
+        }
+
+        return line;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(step: NormalizeStep, line: &str) -> String {
+        Normalizer::new(vec![step]).normalize(line)
+    }
+
+    #[test]
+    fn unicode_forms() {
+        // "é" as "e" and a combining acute accent, and the "ﬁ" ligature
+        assert_eq!(normalize(NormalizeStep::Nfc, "e\u{301} \u{FB01}"), "\u{E9} \u{FB01}");
+        assert_eq!(normalize(NormalizeStep::Nfkc, "e\u{301} \u{FB01}"), "\u{E9} fi");
+    }
+
+    #[test]
+    fn earliest_trailing_comment_marker_wins() {
+        let step = NormalizeStep::StripTrailingComment(vec![String::from("#"), String::from("//")]);
+
+        assert_eq!(normalize(step.clone(), "a // b # c"), "a ");
+        assert_eq!(normalize(step.clone(), "a # b // c"), "a ");
+        assert_eq!(normalize(step, "a"), "a");
+    }
+
+    #[test]
+    fn replace_can_refer_to_capture_groups() {
+        let step = NormalizeStep::Replace(Regex::new(r"(\w+)\.get\((\d+)\)").unwrap(), String::from("$1[$2]"));
+
+        assert_eq!(normalize(step, "x = a.get(1) + b.get(22)"), "x = a[1] + b[22]");
+    }
+
+    #[test]
+    fn squash_chars_only_squashes_the_given_chars() {
+        assert_eq!(normalize(NormalizeStep::SquashChars(vec![' ', '-']), "a   b--c==d"), "a b-c==d");
+    }
+
+    #[test]
+    fn collapse_whitespace_makes_any_run_one_space() {
+        assert_eq!(normalize(NormalizeStep::CollapseWhitespace, "\ta \t b\n"), " a b ");
+    }
+
+    #[test]
+    fn trim_and_case_fold() {
+        assert_eq!(normalize(NormalizeStep::Trim, "  A b  "), "A b");
+        assert_eq!(normalize(NormalizeStep::CaseFold, "ÀbC"), "àbc");
+    }
+
+    #[test]
+    fn steps_have_a_fixed_order() {
+        let normalizer = Normalizer::new(vec![
+            NormalizeStep::CaseFold,
+            NormalizeStep::Trim,
+            NormalizeStep::CollapseWhitespace,
+            NormalizeStep::SquashChars(vec!['-']),
+            NormalizeStep::Replace(Regex::new("B").unwrap(), String::from("x")),
+            NormalizeStep::StripTrailingComment(vec![String::from("#")]),
+            NormalizeStep::Nfkc,
+            NormalizeStep::Nfc,
+        ]);
+        let ranks = normalizer.steps().iter().map(NormalizeStep::rank).collect::<Vec<usize>>();
+
+        assert_eq!(ranks, (0..8).collect::<Vec<usize>>());
+        // The replacement happens before case folding could change "B"
+        assert_eq!(normalizer.normalize(" aB # c "), "ax");
+    }
+
+    #[test]
+    fn replacements_keep_their_order() {
+        let normalizer = Normalizer::new(vec![
+            NormalizeStep::Replace(Regex::new("a").unwrap(), String::from("b")),
+            NormalizeStep::Replace(Regex::new("b").unwrap(), String::from("c")),
+        ]);
+
+        assert_eq!(normalizer.normalize("ab"), "cc");
+    }
+}
@@ -1,16 +1,132 @@
-use std::{collections::HashMap, sync::Mutex};
+use regex::Regex;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
 
-use crate::{counting::FileLocation, pattern::Pattern};
+use crate::{encoding::Encoding, normalize::Normalizer, pattern::{GlobList, Pattern, parse_pattern}};
 
 #[derive(Debug)]
-pub struct Options<'a> {
+pub struct Options {
+    /// Used for files that don't start with a byte order mark
+    pub encoding: Encoding,
     pub line_delimiter: char,
-    pub line_pattern: Pattern<'a>,
-    pub squash_chars: Vec<char>,
+    pub line_pattern: Pattern,
+    pub line_regexes: Vec<Regex>,
+    pub exclude_line_regexes: Vec<Regex>,
+    pub normalizer: Normalizer,
+    /// Used instead of `normalizer` for the files they match; the first match
+    /// wins
+    pub normalizer_overrides: Vec<NormalizerOverride>,
+    /// The directories being searched, which path globs are relative to
+    pub roots: Vec<PathBuf>,
+    pub comments: CommentFilter,
     pub ignore_delimiters: Vec<char>,
-    pub trim_whitespace: bool,
     pub blocks: bool,
+    pub min_block_lines: usize,
+    pub max_block_lines: Option<usize>,
+    pub tokens: bool,
+    pub min_tokens: usize,
+    pub parameterize: bool,
+    pub consistent_renaming: bool,
     pub mode: Mode,
+    /// In remove mode, which instance of a duplicated line survives
+    pub keep: Keep,
+    /// In remove mode, report what would change instead of writing files
+    pub dry_run: Option<DryRun>,
+    /// In remove mode, keep each file's previous version at its path with
+    /// this appended
+    pub backup_suffix: Option<String>,
+    /// Only report duplicate groups that aren't in this baseline file
+    pub baseline: Option<PathBuf>,
+    /// Record every duplicate group found in this baseline file
+    pub write_baseline: Option<PathBuf>,
+}
+
+impl Options {
+    /// The normalizer to use for lines from `file_path`
+    pub fn normalizer_for(&self, file_path: &Path) -> &Normalizer {
+        let relative_path = self.relative_path(file_path);
+
+        self.normalizer_overrides.iter()
+            .find(|o| o.paths.is_match(relative_path))
+            .map(|o| &o.normalizer)
+            .unwrap_or(&self.normalizer)
+    }
+
+    /// `file_path` relative to the first of `roots` it's under
+    pub fn relative_path<'a>(&self, file_path: &'a Path) -> &'a Path {
+        self.roots.iter()
+            .find_map(|root| file_path.strip_prefix(root).ok())
+            .unwrap_or(file_path)
+    }
+}
+
+/// The same defaults as the command line
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            encoding:           Encoding::Utf8,
+            line_delimiter:     '\n',
+            line_pattern:       parse_pattern("*"),
+            line_regexes:       vec![],
+            exclude_line_regexes: vec![],
+            normalizer:         Normalizer::default(),
+            normalizer_overrides: vec![],
+            roots:              vec![],
+            comments:           CommentFilter::Keep,
+            ignore_delimiters:  vec![],
+            blocks:             false,
+            min_block_lines:    2,
+            max_block_lines:    None,
+            tokens:             false,
+            min_tokens:         100,
+            parameterize:       false,
+            consistent_renaming: false,
+            mode:               Mode::AllFiles,
+            keep:               Keep::First,
+            dry_run:            None,
+            backup_suffix:      None,
+            baseline:           None,
+            write_baseline:     None,
+        }
+    }
+}
+
+/// Normalization for a subset of files that differs from the rest (eg.
+/// trimming whitespace in Python files but not in Markdown)
+#[derive(Debug)]
+pub struct NormalizerOverride {
+    pub paths: GlobList,
+    pub normalizer: Normalizer,
+}
+
+/// Which rules to apply when deciding whether a file or directory should be
+/// skipped during the directory walk
+#[derive(Clone, Copy, Debug)]
+pub struct WalkOptions {
+    pub hidden: bool,
+    pub git_ignore: bool,
+    pub ignore_files: bool,
+    pub git_exclude: bool,
+}
+
+/// Every rule applies by default
+impl Default for WalkOptions {
+    fn default() -> Self {
+        WalkOptions {
+            hidden:             false,
+            git_ignore:         true,
+            ignore_files:       true,
+            git_exclude:        true,
+        }
+    }
+}
+
+/// What to do with comments in files whose language is recognized
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommentFilter {
+    Keep,
+    Strip,
+    Only,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -18,20 +134,75 @@ pub enum Mode {
     AllFiles,
     SameFile,
     RemoveDuplicates,
+    RemoveDuplicatesAcrossFiles,
 }
 
-pub enum SearchResult {
-    AllFiles(Mutex<HashMap<String, Vec<FileLocation>>>),
-    SameFile(Mutex<Vec<HashMap<String, Vec<FileLocation>>>>),
-    RemoveDuplicates,
+/// Which instance of a duplicated line to keep when removing duplicates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Keep {
+    First,
+    Last,
+    /// The first instance of the most frequent raw form of the line, for
+    /// when normalization makes differently written lines compare equal
+    MostCommon,
+}
+
+/// How to report the changes a dry run would make
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DryRun {
+    Diff,
+    Count,
+}
+
+/// Limits on how much duplication is acceptable. Breaking any of them makes
+/// the run exit with a failure status, so CI builds can be gated on them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Thresholds {
+    pub fail_on_duplicates: bool,
+    pub max_duplicate_lines: Option<usize>,
+    pub max_duplication_percent: Option<f64>,
 }
 
-impl SearchResult {
-    pub fn from_mode(mode: Mode) -> Self {
-        match mode {
-            Mode::AllFiles => Self::AllFiles(Mutex::new(HashMap::new())),
-            Mode::SameFile => Self::SameFile(Mutex::new(Vec::new())),
-            Mode::RemoveDuplicates => Self::RemoveDuplicates,
+/// What each duplicate is made of
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateUnit {
+    Lines,
+    Blocks,
+    Tokens,
+}
+
+impl DuplicateUnit {
+    pub const ALL: [DuplicateUnit; 3] = [DuplicateUnit::Lines, DuplicateUnit::Blocks, DuplicateUnit::Tokens];
+
+    /// The name it's given in reports, which is also part of fingerprints
+    pub fn name(self) -> &'static str {
+        match self {
+            DuplicateUnit::Lines => "lines",
+            DuplicateUnit::Blocks => "blocks",
+            DuplicateUnit::Tokens => "tokens",
         }
     }
-}
\ No newline at end of file
+}
+
+/// How results are written to standard output
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    /// A single JSON document (see `report::Report`)
+    Json,
+    /// One JSON object per line: a header, then each duplicate group or
+    /// changed file, then a summary
+    Ndjson,
+    /// SARIF 2.1.0, for code scanning tools
+    Sarif,
+}
+
+/// The duplicate lines removed (or that would be removed) from one file
+#[derive(Debug, Serialize)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub removed_lines: usize,
+    /// A unified diff of the change, if one was asked for
+    pub diff: Option<String>,
+}
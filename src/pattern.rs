@@ -1,18 +1,22 @@
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use std::path::Path;
+
 
 /// A pattern against which strings can be matched
-pub type Pattern<'a> = Vec<&'a str>;
+pub type Pattern = Vec<String>;
 
 pub fn parse_pattern(pattern_str: &str) -> Pattern {
-    pattern_str.split('*').collect()
+    pattern_str.split('*').map(String::from).collect()
 }
 
-pub fn matches<'a>(s: &str, pattern: &Pattern) -> bool {
+pub fn matches(s: &str, pattern: &Pattern) -> bool {
     let mut remainder = Some(s);
   
     for segment in pattern {
         match remainder {
             Some(slice) => {
-                match slice.find(segment) {
+                match slice.find(segment.as_str()) {
                     Some(index) => remainder = slice.get(index..),
                     None => return false
                 }
@@ -22,4 +26,166 @@ pub fn matches<'a>(s: &str, pattern: &Pattern) -> bool {
     }
   
     return true;
-}
\ No newline at end of file
+}
+
+/// A list of globs to match paths against. Every glob is anchored to the
+/// search root and matched against the whole relative path, so '*.rs' only
+/// matches files at the top level and '**/*.rs' matches them anywhere. A glob
+/// ending in '/' only matches directories, and everything inside them.
+#[derive(Clone, Debug, Default)]
+pub struct GlobList {
+    paths: GlobSet,
+    dirs: GlobSet,
+}
+
+impl GlobList {
+    pub fn new<'a>(globs: impl IntoIterator<Item=&'a str>) -> Result<Self, globset::Error> {
+        let mut paths = GlobSetBuilder::new();
+        let mut dirs = GlobSetBuilder::new();
+
+        for glob in globs {
+            let anchored = glob.trim_start_matches('/');
+
+            match anchored.strip_suffix('/') {
+                Some(dir_glob) => dirs.add(build_glob(dir_glob)?),
+                None => paths.add(build_glob(anchored)?),
+            };
+        }
+
+        Ok(GlobList {
+            paths: paths.build()?,
+            dirs: dirs.build()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.dirs.is_empty()
+    }
+
+    /// Whether a file matches. `relative_path` is relative to the search root.
+    pub fn is_match(&self, relative_path: &Path) -> bool {
+        self.paths.is_match(relative_path) || self.in_matching_dir(relative_path.parent())
+    }
+
+    /// Whether a directory matches. `relative_path` is relative to the search
+    /// root.
+    pub fn is_dir_match(&self, relative_path: &Path) -> bool {
+        self.paths.is_match(relative_path) || self.in_matching_dir(Some(relative_path))
+    }
+
+    /// Whether `dir` or any directory above it matches a directory-only glob
+    fn in_matching_dir(&self, dir: Option<&Path>) -> bool {
+        dir.into_iter()
+            .flat_map(Path::ancestors)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .any(|dir| self.dirs.is_match(dir))
+    }
+}
+
+/// Check that `glob` is valid, for use as a clap validator
+pub fn validate_glob(glob: String) -> Result<(), String> {
+    build_glob(&glob)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Check that `regex` is valid, for use as a clap validator
+pub fn validate_regex(regex: String) -> Result<(), String> {
+    Regex::new(&regex)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn build_glob(glob: &str) -> Result<Glob, globset::Error> {
+    GlobBuilder::new(glob)
+        .literal_separator(true)
+        .build()
+}
+
+/// Decides which paths will be searched, based on lists of globs to include
+/// and exclude
+#[derive(Clone, Debug, Default)]
+pub struct PathFilter {
+    pub include: GlobList,
+    pub exclude: GlobList,
+}
+
+impl PathFilter {
+
+    /// Whether a directory should be descended into. Only exclusions are
+    /// considered here, because included files may live anywhere below it.
+    pub fn allows_dir(&self, relative_path: &Path) -> bool {
+        !self.exclude.is_dir_match(relative_path)
+    }
+
+    pub fn allows_file(&self, relative_path: &Path) -> bool {
+        (self.include.is_empty() || self.include.is_match(relative_path))
+            && !self.exclude.is_match(relative_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn globs(globs: &[&str]) -> GlobList {
+        GlobList::new(globs.iter().copied()).unwrap()
+    }
+
+    #[test]
+    fn globs_are_anchored_to_the_root() {
+        let list = globs(&["*.rs"]);
+
+        assert!(list.is_match(Path::new("main.rs")));
+        assert!(!list.is_match(Path::new("src/main.rs")));
+        assert!(!list.is_match(Path::new("main.rs.bak")));
+    }
+
+    #[test]
+    fn double_star_matches_any_depth() {
+        let list = globs(&["**/*.rs"]);
+
+        assert!(list.is_match(Path::new("main.rs")));
+        assert!(list.is_match(Path::new("src/a/main.rs")));
+    }
+
+    #[test]
+    fn leading_slash_is_the_root() {
+        let list = globs(&["/src/*.rs"]);
+
+        assert!(list.is_match(Path::new("src/main.rs")));
+        assert!(!list.is_match(Path::new("lib/src/main.rs")));
+    }
+
+    #[test]
+    fn trailing_slash_only_matches_directories() {
+        let list = globs(&["tests/"]);
+
+        assert!(list.is_dir_match(Path::new("tests")));
+        assert!(list.is_match(Path::new("tests/a.rs")));
+        assert!(list.is_match(Path::new("tests/deep/a.rs")));
+        assert!(!list.is_match(Path::new("tests")));
+        assert!(!list.is_dir_match(Path::new("src/tests")));
+    }
+
+    #[test]
+    fn excluded_directories_are_not_entered() {
+        let filter = PathFilter {
+            include: GlobList::default(),
+            exclude: globs(&["vendor/", "**/*.min.js"]),
+        };
+
+        assert!(!filter.allows_dir(Path::new("vendor")));
+        assert!(filter.allows_dir(Path::new("src")));
+        assert!(!filter.allows_file(Path::new("src/app.min.js")));
+        assert!(filter.allows_file(Path::new("src/app.js")));
+    }
+
+    #[test]
+    fn basic_patterns_are_unanchored() {
+        let pattern = parse_pattern("fn*(");
+
+        assert!(matches("pub fn main() {", &pattern));
+        assert!(!matches("let x = 1;", &pattern));
+    }
+}
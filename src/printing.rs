@@ -4,18 +4,17 @@
 use syntect::{easy::HighlightLines, highlighting::Style, parsing::{SyntaxReference, SyntaxSet}, util::{as_24_bit_terminal_escaped}};
 
 use crate::counting::FileLocation;
+use crate::encoding::escape_raw_bytes;
 
 #[cfg(feature = "syntax-highlighting")]
-pub fn print_occurences_highlighted(line: &str, occurences: &Vec<FileLocation>, mut write: impl FnMut(&str) -> (), ps: &SyntaxSet, mut h: HighlightLines) {
+pub fn print_occurences_highlighted(line: &str, occurences: &[FileLocation], mut write: impl FnMut(&str), ps: &SyntaxSet, mut h: HighlightLines) {
     
     // Syntax-color if possible
-    let ranges: Vec<(Style, &str)> = h.highlight(line, ps);
+    let escaped = escape_raw_bytes(line);
+    let ranges: Vec<(Style, &str)> = h.highlight(&escaped, ps);
 
     write(&as_24_bit_terminal_escaped(&ranges[..], false));
-
-    for loc in occurences {
-        write(&format!("\n\t{}", &loc));
-    }
+    print_locations(line, occurences, write);
 }
 
 #[cfg(feature = "syntax-highlighting")]
@@ -24,12 +23,56 @@ pub struct Highlighter<'a> {
     pub h: HighlightLines<'a>
 }
 
-fn print_occurences(line: &str, occurences: &Vec<FileLocation>, mut write: impl FnMut(&str) -> ()) {
-    write(line);
+fn print_occurences(line: &str, occurences: &[FileLocation], mut write: impl FnMut(&str)) {
+    write(&escape_raw_bytes(line));
+    print_locations(line, occurences, write);
+}
+
+/// Print each location, grouped under the raw text found there if not all of
+/// the occurrences had the same raw text
+fn print_locations(line: &str, occurences: &[FileLocation], mut write: impl FnMut(&str)) {
+    let variants = raw_variants(line, occurences);
+
+    if variants.len() > 1 {
+        for (raw_text, locations) in variants {
+            let raw_text = escape_raw_bytes(raw_text);
+
+            if raw_text.contains('\n') {
+                write("\n\tvariant:");
+
+                for raw_line in raw_text.lines() {
+                    write(&format!("\n\t| {}", raw_line));
+                }
+            } else {
+                write(&format!("\n\tvariant: {}", raw_text));
+            }
+
+            for loc in locations {
+                write(&format!("\n\t\t{}", &loc));
+            }
+        }
+    } else {
+        for loc in occurences {
+            write(&format!("\n\t{}", &loc));
+        }
+    }
+}
+
+/// Group occurrences by the raw text found at each one, in order of first
+/// appearance
+pub fn raw_variants<'a>(line: &'a str, occurences: &'a [FileLocation]) -> Vec<(&'a str, Vec<&'a FileLocation>)> {
+    let mut variants: Vec<(&str, Vec<&FileLocation>)> = Vec::new();
 
     for loc in occurences {
-        write(&format!("\n\t{}", &loc));
+        let raw_text = loc.raw_text.as_deref().unwrap_or(line);
+
+        match variants.iter_mut().find(|(existing, _)| *existing == raw_text) {
+            Some((_, locations)) => locations.push(loc),
+            None => variants.push((raw_text, vec![loc])),
+        }
     }
+
+    variants
 }
 
 
@@ -0,0 +1,528 @@
+use std::{collections::HashMap, hash::Hash, ops::RangeInclusive, path::PathBuf};
+
+use crate::counting::{FileLocation, Occurrences};
+
+/// A file's contents as a sequence of units (tokens or lines), to be searched
+/// for repeated runs
+#[derive(Debug)]
+pub struct Sequence {
+    pub path: PathBuf,
+    pub units: Vec<Unit>,
+}
+
+/// One token or line. `text` is what gets compared; if that was abstracted
+/// from something else (eg. an identifier replaced by a placeholder), the
+/// original is kept in `raw`.
+#[derive(Debug)]
+pub struct Unit {
+    pub text: String,
+    pub raw: Option<String>,
+    pub line_number: usize,
+    /// Column (in characters, starting at 1) where the unit begins
+    pub column: usize,
+}
+
+impl Unit {
+    pub fn raw_text(&self) -> &str {
+        self.raw.as_deref().unwrap_or(&self.text)
+    }
+}
+
+/// A run of units that occurs more than once. Each occurrence is given as
+/// (index of the sequence, index of the first unit within it).
+#[derive(Debug)]
+pub struct Repeat {
+    pub occurrences: Vec<(usize, usize)>,
+    pub len: usize,
+}
+
+/// Find every maximal run of units that is repeated in `sequences` and whose
+/// length is within `lengths`, and record each occurrence as a range of
+/// lines. The key for each run is the text of its units joined with
+/// `separator`.
+///
+/// If `consistent_renaming` is set, occurrences of a run only count as
+/// duplicates of each other if their raw units map one-to-one (eg. every `a`
+/// in one is an `x` in the other). Occurrences that are renamed differently
+/// are split into separate groups, and groups after the first get a suffix
+/// on their key to keep them apart. The split happens after the maximal runs
+/// have been chosen, so an occurrence left on its own is dropped even if a
+/// shorter run inside it was renamed consistently with another occurrence.
+pub fn count_repeats(sequences: &[Sequence], lengths: RangeInclusive<usize>, separator: &str, consistent_renaming: bool) -> Occurrences {
+    let texts = sequences.iter()
+        .map(|sequence| sequence.units.iter().map(|unit| unit.text.as_str()).collect())
+        .collect::<Vec<Vec<&str>>>();
+
+    let mut records: Occurrences = HashMap::new();
+
+    let repeats = find_repeats(&texts, *lengths.start())
+        .into_iter()
+        .filter(|repeat| lengths.contains(&repeat.len));
+
+    for repeat in repeats {
+        let (first_sequence, first_start) = repeat.occurrences[0];
+        let key = texts[first_sequence][first_start..first_start + repeat.len].join(separator);
+
+        let groups = if consistent_renaming {
+            group_by_renaming(sequences, &repeat)
+        } else {
+            vec![repeat.occurrences.clone()]
+        };
+
+        for (group_index, group) in groups.into_iter().filter(|group| group.len() > 1).enumerate() {
+            let group_key = match group_index {
+                0 => key.clone(),
+                n => format!("{} [renaming {}]", key, n + 1),
+            };
+
+            let locations = group.into_iter()
+                .map(|(sequence_index, start)| {
+                    let sequence = &sequences[sequence_index];
+                    let units = &sequence.units[start..start + repeat.len];
+
+                    let raw_text = if units.iter().any(|unit| unit.raw.is_some()) {
+                        Some(units.iter().map(Unit::raw_text).collect::<Vec<&str>>().join(separator))
+                    } else {
+                        None
+                    };
+
+                    FileLocation {
+                        path: sequence.path.clone(),
+                        line_number: units[0].line_number,
+                        column: units[0].column,
+                        end_line_number: units[repeat.len - 1].line_number,
+                        raw_text,
+                    }
+                });
+
+            records.entry(group_key).or_default().extend(locations);
+        }
+    }
+
+    return records;
+}
+
+/// Split the occurrences of `repeat` into groups whose raw units are renamed
+/// consistently. Each occurrence is described by, for every unit that has a
+/// raw form, the position in the run where that raw form first appears; two
+/// occurrences are consistent renamings of each other exactly when their
+/// descriptions match.
+fn group_by_renaming(sequences: &[Sequence], repeat: &Repeat) -> Vec<Vec<(usize, usize)>> {
+    let mut groups: Vec<Vec<(usize, usize)>> = Vec::new();
+    let mut group_indices: HashMap<Vec<usize>, usize> = HashMap::new();
+
+    for &(sequence_index, start) in &repeat.occurrences {
+        let units = &sequences[sequence_index].units[start..start + repeat.len];
+
+        let mut first_seen: HashMap<&str, usize> = HashMap::new();
+        let renaming = units.iter()
+            .enumerate()
+            .filter_map(|(index, unit)| unit.raw.as_deref()
+                .map(|raw| *first_seen.entry(raw).or_insert(index)))
+            .collect::<Vec<usize>>();
+
+        let next_index = groups.len();
+        let group_index = *group_indices.entry(renaming).or_insert(next_index);
+
+        if group_index == next_index {
+            groups.push(Vec::new());
+        }
+
+        groups[group_index].push((sequence_index, start));
+    }
+
+    return groups;
+}
+
+/// Find every run of at least `min_len` units that occurs more than once
+/// across `sequences` and can't be extended in either direction without
+/// losing an occurrence. Runs never span two sequences, and overlapping
+/// occurrences of a run within one sequence are only counted once.
+///
+/// This builds a suffix array over all of the sequences, so it takes
+/// O(n log² n) time in the total number of units rather than comparing every
+/// pair of runs.
+pub fn find_repeats<T: Eq + Hash>(sequences: &[Vec<T>], min_len: usize) -> Vec<Repeat> {
+    let min_len = std::cmp::max(min_len, 1);
+
+    // Intern units as integers, and give each sequence a unique terminator
+    // so that no repeat can cross from one sequence into the next
+    let mut ids: HashMap<&T, u32> = HashMap::new();
+    for unit in sequences.iter().flatten() {
+        let next_id = ids.len() as u32;
+        ids.entry(unit).or_insert(next_id);
+    }
+
+    let mut text = Vec::new();
+    let mut origins = Vec::new();
+    for (sequence_index, sequence) in sequences.iter().enumerate() {
+        for (unit_index, unit) in sequence.iter().enumerate() {
+            text.push(ids[unit]);
+            origins.push((sequence_index, unit_index));
+        }
+
+        text.push(ids.len() as u32 + sequence_index as u32);
+        origins.push((sequence_index, sequence.len()));
+    }
+
+    let suffixes = suffix_array(&text);
+    let lcp = longest_common_prefixes(&text, &suffixes);
+
+    let mut repeats = Vec::new();
+
+    for interval in lcp_intervals(&text, &suffixes, &lcp) {
+        if interval.len < min_len || !interval.left_diverse {
+            continue;
+        }
+
+        // Positions in `text` are in the same order as (sequence, index)
+        let mut positions = suffixes[interval.first..=interval.last].to_vec();
+        positions.sort_unstable();
+
+        let mut kept: Vec<usize> = Vec::with_capacity(positions.len());
+        for position in positions {
+            let overlaps = kept.last()
+                .map(|&start| position < start + interval.len)
+                .unwrap_or(false);
+
+            if !overlaps {
+                kept.push(position);
+            }
+        }
+
+        // Dropping overlapping occurrences can leave ones that all extend
+        // the same way, in which case the longer run is reported instead
+        if kept.len() > 1 && !can_extend(&text, &kept, interval.len) {
+            repeats.push(Repeat {
+                occurrences: kept.into_iter().map(|position| origins[position]).collect(),
+                len: interval.len,
+            });
+        }
+    }
+
+    return repeats;
+}
+
+/// Whether every run of `len` symbols starting at `starts` (sorted, and not
+/// overlapping) is preceded by the same symbol, or followed by the same
+/// symbol, without the runs overlapping once they take it in
+fn can_extend(text: &[u32], starts: &[usize], len: usize) -> bool {
+    if starts.windows(2).any(|pair| pair[1] == pair[0] + len) {
+        return false;
+    }
+
+    let all_same = |symbol_at: &dyn Fn(usize) -> Option<u32>| {
+        let first = symbol_at(starts[0]);
+        first.is_some() && starts.iter().all(|&start| symbol_at(start) == first)
+    };
+
+    all_same(&|start| start.checked_sub(1).map(|i| text[i]))
+        || all_same(&|start| text.get(start + len).copied())
+}
+
+/// Sort the suffixes of `text` by prefix doubling. Every symbol of `text`
+/// that terminates a sequence is unique, so all suffixes are distinct.
+fn suffix_array(text: &[u32]) -> Vec<usize> {
+    let n = text.len();
+    if n == 0 {
+        return vec![];
+    }
+
+    let mut suffixes = (0..n).collect::<Vec<usize>>();
+    let mut rank = text.iter().map(|&symbol| symbol as usize).collect::<Vec<usize>>();
+    let mut next_rank = vec![0; n];
+    let mut k = 1;
+
+    loop {
+        {
+            let key = |i: usize| (rank[i], if i + k < n { rank[i + k] + 1 } else { 0 });
+
+            suffixes.sort_unstable_by_key(|&i| key(i));
+
+            next_rank[suffixes[0]] = 0;
+            for w in 1..n {
+                let is_new = key(suffixes[w - 1]) < key(suffixes[w]);
+                next_rank[suffixes[w]] = next_rank[suffixes[w - 1]] + is_new as usize;
+            }
+        }
+
+        std::mem::swap(&mut rank, &mut next_rank);
+
+        if rank[suffixes[n - 1]] == n - 1 {
+            return suffixes;
+        }
+
+        k *= 2;
+    }
+}
+
+/// Kasai's algorithm: `lcp[i]` is the length of the common prefix of the
+/// suffixes at `suffixes[i - 1]` and `suffixes[i]` (and `lcp[0]` is 0)
+fn longest_common_prefixes(text: &[u32], suffixes: &[usize]) -> Vec<usize> {
+    let n = text.len();
+    let mut rank = vec![0; n];
+    for (i, &suffix) in suffixes.iter().enumerate() {
+        rank[suffix] = i;
+    }
+
+    let mut lcp = vec![0; n];
+    let mut h = 0;
+    for position in 0..n {
+        if rank[position] > 0 {
+            let previous = suffixes[rank[position] - 1];
+
+            while position + h < n && previous + h < n && text[position + h] == text[previous + h] {
+                h += 1;
+            }
+
+            lcp[rank[position]] = h;
+            h = h.saturating_sub(1);
+        } else {
+            h = 0;
+        }
+    }
+
+    return lcp;
+}
+
+/// A range of the suffix array whose suffixes all share a prefix of length
+/// `len`, which no larger range shares
+struct LcpInterval {
+    len: usize,
+    first: usize,
+    last: usize,
+    /// Whether the suffixes are preceded by different symbols (ie. the shared
+    /// prefix can't be extended to the left)
+    left_diverse: bool,
+}
+
+#[derive(Clone, Copy)]
+enum LeftSymbols {
+    None,
+    One(u32),
+    Many,
+}
+
+impl LeftSymbols {
+    fn merge(self, other: LeftSymbols) -> LeftSymbols {
+        match (self, other) {
+            (LeftSymbols::None, other) | (other, LeftSymbols::None) => other,
+            (LeftSymbols::One(a), LeftSymbols::One(b)) if a == b => self,
+            _ => LeftSymbols::Many,
+        }
+    }
+}
+
+struct OpenInterval {
+    len: usize,
+    first: usize,
+    left: LeftSymbols,
+}
+
+/// Enumerate the lcp-intervals of the suffix array bottom-up with a stack,
+/// tracking which symbols precede the suffixes in each one
+fn lcp_intervals(text: &[u32], suffixes: &[usize], lcp: &[usize]) -> Vec<LcpInterval> {
+    let mut intervals = Vec::new();
+    let mut stack = vec![OpenInterval { len: 0, first: 0, left: LeftSymbols::None }];
+
+    for (leaf, &suffix) in suffixes.iter().enumerate() {
+        let leaf_left = match suffix {
+            0 => LeftSymbols::Many,
+            position => LeftSymbols::One(text[position - 1]),
+        };
+
+        let top = stack.last_mut().unwrap();
+        top.left = top.left.merge(leaf_left);
+
+        let len = lcp.get(leaf + 1).copied().unwrap_or(0);
+        let mut first = leaf;
+        let mut popped_left = leaf_left;
+
+        while len < stack.last().unwrap().len {
+            let closed = stack.pop().unwrap();
+
+            intervals.push(LcpInterval {
+                len: closed.len,
+                first: closed.first,
+                last: leaf,
+                left_diverse: matches!(closed.left, LeftSymbols::Many),
+            });
+
+            first = closed.first;
+            popped_left = closed.left;
+
+            let parent = stack.last_mut().unwrap();
+            parent.left = parent.left.merge(closed.left);
+        }
+
+        if len > stack.last().unwrap().len {
+            stack.push(OpenInterval { len, first, left: popped_left });
+        }
+    }
+
+    return intervals;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each repeat as (length, occurrences), sorted
+    fn repeats(sequences: &[&str], min_len: usize) -> Vec<(usize, Vec<(usize, usize)>)> {
+        let sequences = sequences.iter()
+            .map(|sequence| sequence.chars().collect())
+            .collect::<Vec<Vec<char>>>();
+
+        let mut repeats = find_repeats(&sequences, min_len).into_iter()
+            .map(|repeat| (repeat.len, repeat.occurrences))
+            .collect::<Vec<_>>();
+        repeats.sort();
+
+        repeats
+    }
+
+    fn sequence(path: &str, units: &[(&str, Option<&str>)]) -> Sequence {
+        Sequence {
+            path: PathBuf::from(path),
+            units: units.iter()
+                .enumerate()
+                .map(|(index, &(text, raw))| Unit {
+                    text: text.to_owned(),
+                    raw: raw.map(str::to_owned),
+                    line_number: index + 1,
+                    column: 1,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn empty_input() {
+        assert!(repeats(&[], 1).is_empty());
+        assert!(repeats(&["", ""], 1).is_empty());
+    }
+
+    #[test]
+    fn suffix_array_is_sorted() {
+        let text = [2, 1, 2, 1, 0];
+        let suffixes = suffix_array(&text);
+
+        assert_eq!(suffixes, [4, 3, 1, 2, 0]);
+        assert_eq!(longest_common_prefixes(&text, &suffixes), [0, 0, 1, 0, 2]);
+    }
+
+    #[test]
+    fn single_sequence() {
+        assert_eq!(repeats(&["abcxabcy"], 2), [(3, vec![(0, 0), (0, 4)])]);
+        assert!(repeats(&["abcdef"], 1).is_empty());
+    }
+
+    #[test]
+    fn only_maximal_runs() {
+        // "bc" is reported on its own because it also follows 'x', but "ab"
+        // and "c" alone are always part of "abc"
+        assert_eq!(repeats(&["abcd", "xbcy", "abcz"], 1), [
+            (2, vec![(0, 1), (1, 1), (2, 1)]),
+            (3, vec![(0, 0), (2, 0)]),
+        ]);
+        assert_eq!(repeats(&["abcd", "xbcy", "abcz"], 3), [(3, vec![(0, 0), (2, 0)])]);
+    }
+
+    #[test]
+    fn overlapping_occurrences_count_once() {
+        // "aaa" occurs at 0 and 1, but those overlap
+        assert_eq!(repeats(&["aaaa"], 2), [(2, vec![(0, 0), (0, 2)])]);
+        assert!(repeats(&["aaa"], 2).is_empty());
+        assert_eq!(repeats(&["abab", "abab"], 3), [(4, vec![(0, 0), (1, 0)])]);
+    }
+
+    #[test]
+    fn runs_never_cross_sequences() {
+        assert_eq!(repeats(&["xab", "cdy", "abcd"], 2), [
+            (2, vec![(0, 1), (2, 0)]),
+            (2, vec![(1, 0), (2, 2)]),
+        ]);
+    }
+
+    #[test]
+    fn repeats_at_the_start_of_sequences() {
+        assert_eq!(repeats(&["abc", "abd"], 1), [(2, vec![(0, 0), (1, 0)])]);
+    }
+
+    #[test]
+    fn every_repeat_is_real_and_maximal() {
+        for seed in 0..20u32 {
+            // Pseudo-random sequences over a small alphabet, so there are
+            // lots of repeats of different lengths
+            let mut state = seed;
+            let mut next = || {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) % 3
+            };
+            let sequences = (0..5)
+                .map(|_| (0..40).map(|_| next()).collect())
+                .collect::<Vec<Vec<u32>>>();
+
+            for repeat in find_repeats(&sequences, 2) {
+                let run = |&(sequence, start): &(usize, usize)| &sequences[sequence][start..start + repeat.len];
+                let first = run(&repeat.occurrences[0]);
+
+                assert!(repeat.len >= 2);
+                assert!(repeat.occurrences.iter().all(|occurrence| run(occurrence) == first));
+                assert!(repeat.occurrences.windows(2).all(|pair| pair[0].0 != pair[1].0 || pair[1].1 >= pair[0].1 + repeat.len));
+
+                // Extending the run by one unit would either make two of its
+                // occurrences overlap, or lose one of them
+                let touching = repeat.occurrences.windows(2).any(|pair| pair[0].0 == pair[1].0 && pair[1].1 == pair[0].1 + repeat.len);
+                let before = repeat.occurrences.iter()
+                    .map(|&(sequence, start)| start.checked_sub(1).map(|i| sequences[sequence][i]))
+                    .collect::<Vec<_>>();
+                let after = repeat.occurrences.iter()
+                    .map(|&(sequence, start)| sequences[sequence].get(start + repeat.len).copied())
+                    .collect::<Vec<_>>();
+                let diverse = |symbols: &[Option<u32>]| symbols.contains(&None) || symbols.windows(2).any(|pair| pair[0] != pair[1]);
+
+                assert!(touching || (diverse(&before) && diverse(&after)));
+            }
+        }
+    }
+
+    #[test]
+    fn counted_runs_become_line_ranges() {
+        let sequences = [
+            sequence("a", &[("x", None), ("y", None), ("z", None)]),
+            sequence("b", &[("w", None), ("x", None), ("y", None)]),
+        ];
+
+        let records = count_repeats(&sequences, 2..=usize::MAX, "\n", false);
+        let locations = &records["x\ny"];
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(locations.iter().map(|l| (l.path.to_str().unwrap(), l.line_number, l.end_line_number)).collect::<Vec<_>>(),
+            [("a", 1, 2), ("b", 2, 3)]);
+        assert!(count_repeats(&sequences, 3..=usize::MAX, "\n", false).is_empty());
+        assert!(count_repeats(&sequences, 1..=1, "\n", false).is_empty());
+    }
+
+    #[test]
+    fn inconsistent_renamings_are_split() {
+        let id = |raw| ("$id", Some(raw));
+        let sequences = [
+            sequence("a", &[id("a"), ("=", None), id("a")]),
+            sequence("b", &[id("b"), ("=", None), id("b")]),
+            sequence("c", &[id("c"), ("=", None), id("d")]),
+            sequence("d", &[id("e"), ("=", None), id("f")]),
+        ];
+
+        let records = count_repeats(&sequences, 3..=usize::MAX, " ", true);
+        let paths = |key: &str| records[key].iter().map(|l| l.path.to_str().unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(paths("$id = $id"), ["a", "b"]);
+        assert_eq!(paths("$id = $id [renaming 2]"), ["c", "d"]);
+        assert_eq!(records["$id = $id"][0].raw_text.as_deref(), Some("a = a"));
+
+        let records = count_repeats(&sequences, 3..=usize::MAX, " ", false);
+
+        assert_eq!(records["$id = $id"].len(), 4);
+    }
+}
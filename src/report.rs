@@ -0,0 +1,422 @@
+use std::{io::{self, Write}, path::Path};
+
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::{counting::FileLocation, encoding::escape_raw_bytes, error::FileError, options::{DuplicateUnit, FileChange, OutputFormat, Thresholds}, printing::raw_variants};
+
+/// Bumped whenever a field is removed or changes meaning. Adding fields
+/// doesn't change the version.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Everything a run found, in a form that can be written out as JSON
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub schema_version: u32,
+    /// What each duplicate group is made of: "lines", "blocks" or "tokens"
+    pub unit: DuplicateUnit,
+    pub groups: Vec<DuplicateGroup>,
+    /// Files changed (or that would be changed) in remove mode
+    pub changes: Vec<FileChange>,
+    /// Files and directories that couldn't be searched
+    pub errors: Vec<FailedFile>,
+    pub summary: Summary,
+}
+
+/// One duplicated line (or block, or run of tokens) and everywhere it occurs
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    /// Identifies the group across runs (see `baseline::fingerprint`)
+    pub fingerprint: String,
+    /// The normalized text the occurrences were compared by
+    pub text: String,
+    /// The distinct raw forms found at the occurrences, in order of first
+    /// appearance
+    pub variants: Vec<String>,
+    pub occurrences: Vec<Occurrence>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Occurrence {
+    pub path: String,
+    pub line: usize,
+    pub end_line: usize,
+    pub column: usize,
+    /// Index into the group's `variants`
+    pub variant: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+impl From<&FileError> for FailedFile {
+    fn from(error: &FileError) -> Self {
+        FailedFile {
+            path: error.path.to_string_lossy().into_owned(),
+            reason: error.kind.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub files_searched: usize,
+    pub files_with_duplicates: usize,
+    /// Files and directories in `Report::errors`
+    pub files_with_errors: usize,
+    /// Files skipped because they look like binaries
+    pub binary_files: usize,
+    pub duplicate_groups: usize,
+    /// Lines in all of the files searched
+    pub total_lines: usize,
+    /// Lines that are copies of a line found earlier (the first occurrence of
+    /// each group doesn't count), or the lines removed in remove mode
+    pub duplicate_lines: usize,
+    /// In remove mode, the number of lines removed (or that would be)
+    pub removed_lines: usize,
+    pub dry_run: bool,
+    pub timings: Timings,
+}
+
+/// How long a scan took. Files are searched while the rest are still being
+/// found, so listing is part of the total rather than coming before it.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Timings {
+    /// Finding the files to search
+    pub listing_ms: u64,
+    /// The whole scan, from when listing started until the results were
+    /// ready
+    pub total_ms: u64,
+}
+
+impl Summary {
+    /// The percentage of all lines that are duplicates
+    pub fn duplication_percent(&self) -> f64 {
+        if self.total_lines == 0 {
+            0.0
+        } else {
+            self.duplicate_lines as f64 * 100.0 / self.total_lines as f64
+        }
+    }
+}
+
+/// Describe each of `thresholds` that `summary` breaks
+pub fn threshold_violations(summary: &Summary, thresholds: &Thresholds) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    if thresholds.fail_on_duplicates && summary.duplicate_lines > 0 {
+        violations.push(format!("Found {} duplicate lines (--fail_on_duplicates)", summary.duplicate_lines));
+    }
+
+    if let Some(max) = thresholds.max_duplicate_lines.filter(|&max| summary.duplicate_lines > max) {
+        violations.push(format!("Found {} duplicate lines, more than the maximum of {} (--max_duplicate_lines)", summary.duplicate_lines, max));
+    }
+
+    if let Some(max) = thresholds.max_duplication_percent.filter(|&max| summary.duplication_percent() > max) {
+        violations.push(format!("{:.2}% of lines are duplicates, more than the maximum of {}% (--max_duplication_percent)", summary.duplication_percent(), max));
+    }
+
+    return violations;
+}
+
+/// One line of NDJSON output. A header comes first and the summary last.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record<'a> {
+    Header { schema_version: u32, unit: DuplicateUnit },
+    Group(&'a DuplicateGroup),
+    Change(&'a FileChange),
+    Error(&'a FailedFile),
+    Summary(&'a Summary),
+}
+
+impl DuplicateGroup {
+    pub fn new(fingerprint: String, text: &str, locations: &[FileLocation]) -> Self {
+        let mut variants = Vec::new();
+        let mut occurrences = Vec::new();
+
+        for (index, (raw_text, variant_locations)) in raw_variants(text, locations).into_iter().enumerate() {
+            variants.push(escape_raw_bytes(raw_text).into_owned());
+
+            occurrences.extend(variant_locations.into_iter().map(|location| Occurrence {
+                path: location.path.to_string_lossy().into_owned(),
+                line: location.line_number,
+                end_line: location.end_line_number,
+                column: location.column,
+                variant: index,
+            }));
+        }
+
+        occurrences.sort_by(|a, b| (&a.path, a.line, a.column).cmp(&(&b.path, b.line, b.column)));
+
+        DuplicateGroup {
+            fingerprint,
+            text: escape_raw_bytes(text).into_owned(),
+            variants,
+            occurrences,
+        }
+    }
+}
+
+/// Write `report` to `out` as a single JSON document, NDJSON or SARIF. Paths
+/// in SARIF output are given relative to `root_dir`.
+pub fn write_report(report: &Report, format: OutputFormat, root_dir: &Path, mut out: impl Write) -> io::Result<()> {
+    match format {
+        OutputFormat::Sarif => {
+            serde_json::to_writer_pretty(&mut out, &sarif_log(report, root_dir))?;
+            writeln!(out)?;
+        },
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, report)?;
+            writeln!(out)?;
+        },
+        OutputFormat::Ndjson => {
+            let records = std::iter::once(Record::Header { schema_version: report.schema_version, unit: report.unit })
+                .chain(report.groups.iter().map(Record::Group))
+                .chain(report.changes.iter().map(Record::Change))
+                .chain(report.errors.iter().map(Record::Error))
+                .chain(std::iter::once(Record::Summary(&report.summary)));
+
+            for record in records {
+                serde_json::to_writer(&mut out, &record)?;
+                writeln!(out)?;
+            }
+        },
+        OutputFormat::Text => unreachable!("text output isn't a report"),
+    }
+
+    return out.flush();
+}
+
+/// SARIF rule ID and description for a unit of duplicate
+fn sarif_rule(unit: DuplicateUnit) -> (&'static str, &'static str) {
+    match unit {
+        DuplicateUnit::Lines => ("duplicate-line", "Duplicated line"),
+        DuplicateUnit::Blocks => ("duplicate-block", "Duplicated block of lines"),
+        DuplicateUnit::Tokens => ("duplicate-tokens", "Duplicated run of tokens"),
+    }
+}
+
+/// Build a SARIF 2.1.0 log with one result per duplicate group. The first
+/// occurrence is the result's location and the others are related locations.
+fn sarif_log(report: &Report, root_dir: &Path) -> Value {
+    // Every unit has a rule, in the order of `DuplicateUnit::ALL`
+    let rule_index = DuplicateUnit::ALL.iter().position(|&unit| unit == report.unit).unwrap();
+    let (rule_id, description) = sarif_rule(report.unit);
+
+    let rules = DuplicateUnit::ALL.iter()
+        .map(|&unit| sarif_rule(unit))
+        .map(|(id, description)| json!({
+            "id": id,
+            "shortDescription": { "text": description },
+            "defaultConfiguration": { "level": "warning" },
+        }))
+        .collect::<Vec<Value>>();
+
+    let results = report.groups.iter()
+        .map(|group| {
+            let (first, others) = group.occurrences.split_first().unwrap();
+
+            let related_locations = others.iter()
+                .enumerate()
+                .map(|(index, occurrence)| json!({
+                    "id": index + 1,
+                    "message": { "text": "Duplicate" },
+                    "physicalLocation": sarif_location(group, occurrence, root_dir),
+                }))
+                .collect::<Vec<Value>>();
+
+            json!({
+                "ruleId": rule_id,
+                "ruleIndex": rule_index,
+                "level": "warning",
+                "message": {
+                    "text": format!("{} (found {} times)", description, group.occurrences.len()),
+                },
+                "locations": [{ "physicalLocation": sarif_location(group, first, root_dir) }],
+                "relatedLocations": related_locations,
+                "partialFingerprints": { "strainer/v1": group.fingerprint },
+            })
+        })
+        .collect::<Vec<Value>>();
+
+    // Files that couldn't be searched
+    let notifications = report.errors.iter()
+        .map(|error| {
+            let path = Path::new(&error.path);
+
+            json!({
+                "level": "error",
+                "message": { "text": error.reason },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": file_uri_path(path.strip_prefix(root_dir).unwrap_or(path)) },
+                    },
+                }],
+            })
+        })
+        .collect::<Vec<Value>>();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "strainer",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+            "invocations": [{
+                "executionSuccessful": report.errors.is_empty(),
+                "toolExecutionNotifications": notifications,
+            }],
+        }],
+    })
+}
+
+fn sarif_location(group: &DuplicateGroup, occurrence: &Occurrence, root_dir: &Path) -> Value {
+    let path = Path::new(&occurrence.path);
+    let relative_path = path.strip_prefix(root_dir).unwrap_or(path);
+
+    let mut region = json!({
+        "startLine": occurrence.line,
+        "startColumn": occurrence.column,
+        "snippet": { "text": group.variants[occurrence.variant] },
+    });
+
+    if occurrence.end_line > occurrence.line {
+        region["endLine"] = json!(occurrence.end_line);
+    }
+
+    json!({
+        "artifactLocation": { "uri": file_uri_path(relative_path) },
+        "region": region,
+    })
+}
+
+/// Percent-encode `path` for use as a URI, with '/' as the separator.
+/// Relative paths give relative URIs.
+fn file_uri_path(path: &Path) -> String {
+    let segments = path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .filter(|segment| segment != "/")
+        .map(|segment| {
+            segment.bytes()
+                .map(|byte| match byte {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+                    _ => format!("%{:02X}", byte),
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>();
+
+    let uri = segments.join("/");
+
+    if path.is_absolute() {
+        format!("file:///{}", uri)
+    } else {
+        uri
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn report() -> Report {
+        Report {
+            schema_version: SCHEMA_VERSION,
+            unit:           DuplicateUnit::Lines,
+            groups:         vec![DuplicateGroup {
+                                fingerprint: String::from("0123456789abcdef"),
+                                text:        String::from("x"),
+                                variants:    vec![String::from("x")],
+                                occurrences: vec![Occurrence {
+                                    path:     String::from("a.txt"),
+                                    line:     1,
+                                    end_line: 1,
+                                    column:   1,
+                                    variant:  0,
+                                }],
+                            }],
+            changes:        vec![FileChange {
+                                path:          PathBuf::from("b.txt"),
+                                removed_lines: 2,
+                                diff:          None,
+                            }],
+            errors:         vec![FailedFile {
+                                path:   String::from("c.txt"),
+                                reason: String::from("not valid UTF-8"),
+                            }],
+            summary:        Summary::default(),
+        }
+    }
+
+    fn written(format: OutputFormat) -> String {
+        let mut out = Vec::new();
+        write_report(&report(), format, Path::new("."), &mut out).unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn ndjson_records_have_a_fixed_order_and_fields() {
+        let lines = written(OutputFormat::Ndjson).lines().map(String::from).collect::<Vec<String>>();
+
+        assert_eq!(lines, [
+            r#"{"type":"header","schema_version":2,"unit":"lines"}"#,
+            r#"{"type":"group","fingerprint":"0123456789abcdef","text":"x","variants":["x"],"occurrences":[{"path":"a.txt","line":1,"end_line":1,"column":1,"variant":0}]}"#,
+            r#"{"type":"change","path":"b.txt","removed_lines":2,"diff":null}"#,
+            r#"{"type":"error","path":"c.txt","reason":"not valid UTF-8"}"#,
+            concat!(
+                r#"{"type":"summary","files_searched":0,"files_with_duplicates":0,"files_with_errors":0,"binary_files":0,"#,
+                r#""duplicate_groups":0,"total_lines":0,"duplicate_lines":0,"removed_lines":0,"dry_run":false,"#,
+                r#""timings":{"listing_ms":0,"total_ms":0}}"#,
+            ),
+        ]);
+    }
+
+    #[test]
+    fn json_has_a_fixed_layout() {
+        let output = written(OutputFormat::Json);
+        let value = serde_json::from_str::<Value>(&output).unwrap();
+
+        assert_eq!(value, json!({
+            "schema_version": 2,
+            "unit": "lines",
+            "groups": [{
+                "fingerprint": "0123456789abcdef",
+                "text": "x",
+                "variants": ["x"],
+                "occurrences": [{ "path": "a.txt", "line": 1, "end_line": 1, "column": 1, "variant": 0 }],
+            }],
+            "changes": [{ "path": "b.txt", "removed_lines": 2, "diff": null }],
+            "errors": [{ "path": "c.txt", "reason": "not valid UTF-8" }],
+            "summary": {
+                "files_searched": 0,
+                "files_with_duplicates": 0,
+                "files_with_errors": 0,
+                "binary_files": 0,
+                "duplicate_groups": 0,
+                "total_lines": 0,
+                "duplicate_lines": 0,
+                "removed_lines": 0,
+                "dry_run": false,
+                "timings": { "listing_ms": 0, "total_ms": 0 },
+            },
+        }));
+
+        let positions = ["\"schema_version\"", "\"unit\"", "\"groups\"", "\"changes\"", "\"errors\"", "\"summary\""].iter()
+            .map(|key| output.find(key).unwrap())
+            .collect::<Vec<usize>>();
+
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,103 @@
+use std::{ffi::OsString, fs, io::{self, Write}, path::{Path, PathBuf}};
+
+use tempfile::NamedTempFile;
+
+/// Replace the contents of the file at `path` without ever leaving it
+/// half-written: the new contents go to a temporary file in the same
+/// directory, which is flushed to disk and then renamed over the original.
+/// The original's permissions (and on Unix, its owner where possible) carry
+/// over to the new file. If `backup_suffix` is given, the original is first
+/// copied to a file with that suffix appended to its name.
+pub fn write_atomically(path: &Path, contents: &[u8], backup_suffix: Option<&str>) -> Result<(), io::Error> {
+    let metadata = fs::metadata(path)?;
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut temp_file = NamedTempFile::new_in(dir)?;
+    temp_file.write_all(contents)?;
+    temp_file.as_file().sync_all()?;
+    fs::set_permissions(temp_file.path(), metadata.permissions())?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        // Only possible when running with enough privileges; otherwise the
+        // new file just belongs to the current user like any other
+        let _ = std::os::unix::fs::chown(temp_file.path(), Some(metadata.uid()), Some(metadata.gid()));
+    }
+
+    if let Some(suffix) = backup_suffix {
+        fs::copy(path, backup_path(path, suffix))?;
+    }
+
+    temp_file.persist(path).map_err(|e| e.error)?;
+
+    // Make sure the rename itself survives a crash
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+fn backup_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contents_are_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, b"old\nold\n").unwrap();
+
+        write_atomically(&path, b"new\n", None).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new\n");
+        // Nothing else is left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permission_bits_are_kept() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("script.sh");
+        fs::write(&path, b"echo old\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o751)).unwrap();
+
+        write_atomically(&path, b"echo new\n", None).unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o751);
+    }
+
+    #[test]
+    fn backups_hold_the_original_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, b"old\xFF\r\n").unwrap();
+
+        write_atomically(&path, b"new\n", Some(".orig")).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new\n");
+        assert_eq!(fs::read(dir.path().join("a.txt.orig")).unwrap(), b"old\xFF\r\n");
+    }
+
+    #[test]
+    fn missing_files_are_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(write_atomically(&dir.path().join("missing.txt"), b"new\n", None).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
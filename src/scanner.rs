@@ -0,0 +1,780 @@
+use std::collections::{HashMap, HashSet};
+use std::{fs, path::{Path, PathBuf}, time::{Duration, Instant}, sync::atomic::{AtomicUsize, Ordering}};
+
+use crossbeam::channel::{self, Sender};
+use similar::TextDiff;
+
+use crate::baseline::{Baseline, fingerprint};
+use crate::blocks::{block_sequences, count_blocks, count_chunks};
+use crate::counting::{FileLocation, Occurrences, claim_lines, count_lines, line_forms, merge_records, strip_lines};
+use crate::encoding::{DecodedText, decode, escape_raw_bytes};
+use crate::error::{FileError, FileErrorKind, ScanError};
+use crate::gather_paths::{list_files_in_dir, order_by_priority, walk_files};
+use crate::normalize::Normalizer;
+use crate::options::{DryRun, DuplicateUnit, FileChange, Mode, Options, WalkOptions};
+use crate::pattern::{GlobList, PathFilter};
+use crate::repeats::Sequence;
+use crate::report::{Summary, Timings};
+use crate::rewrite::write_atomically;
+use crate::tokens::{count_token_runs, count_tokens, tokenize_file};
+
+/// Searches the files under one or more directories for duplicates (or
+/// removes them, depending on the mode). Everything that isn't set uses the
+/// same default as the command line.
+#[derive(Debug, Default)]
+pub struct Scanner {
+    options: Options,
+    path_filter: PathFilter,
+    walk_options: WalkOptions,
+    /// In cross-file remove mode, files matching earlier globs keep their
+    /// lines over files matching later ones
+    priority: Vec<GlobList>,
+    /// How many files to search at once. Defaults to the number of CPUs.
+    threads: Option<usize>,
+}
+
+impl Scanner {
+    pub fn new() -> Self {
+        Scanner::default()
+    }
+
+    /// Add a directory to search
+    pub fn root(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.roots.push(path.into());
+        self
+    }
+
+    /// Only search files matching these globs
+    pub fn include(mut self, globs: GlobList) -> Self {
+        self.path_filter.include = globs;
+        self
+    }
+
+    /// Skip files and directories matching these globs
+    pub fn exclude(mut self, globs: GlobList) -> Self {
+        self.path_filter.exclude = globs;
+        self
+    }
+
+    pub fn walk_options(mut self, walk_options: WalkOptions) -> Self {
+        self.walk_options = walk_options;
+        self
+    }
+
+    pub fn priority(mut self, priority: Vec<GlobList>) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads.max(1));
+        self
+    }
+
+    pub fn normalizer(mut self, normalizer: Normalizer) -> Self {
+        self.options.normalizer = normalizer;
+        self
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.options.mode = mode;
+        self
+    }
+
+    /// Compare maximal blocks of at least `min_lines` lines instead of single
+    /// lines
+    pub fn blocks(mut self, min_lines: usize) -> Self {
+        self.options.blocks = true;
+        self.options.min_block_lines = min_lines;
+        self
+    }
+
+    /// Compare runs of at least `min_tokens` tokens instead of lines
+    pub fn tokens(mut self, min_tokens: usize) -> Self {
+        self.options.tokens = true;
+        self.options.min_tokens = min_tokens;
+        self
+    }
+
+    /// Replace every option that affects how files are compared. Roots that
+    /// have already been added are kept along with any in `options`.
+    pub fn options(mut self, mut options: Options) -> Self {
+        options.roots.splice(0..0, self.options.roots);
+        self.options = options;
+        self
+    }
+
+    pub fn scan(&self) -> Result<ScanReport, ScanError> {
+        let options = &self.options;
+        let threads = self.threads.unwrap_or_else(default_threads);
+
+        for root in &options.roots {
+            fs::metadata(root).map_err(|e| ScanError::Root(root.clone(), e))?;
+        }
+
+
+        // Search for duplicates
+
+        let start_listing = Instant::now();
+        let totals = Totals::default();
+
+        let (results, search_errors, listing) = if options.mode == Mode::RemoveDuplicatesAcrossFiles {
+            // Which file keeps each line has to be worked out from all of the
+            // files before any of them can be changed
+            let mut files = Vec::new();
+            let mut listing = Listing::default();
+
+            for root in &options.roots {
+                let (root_files, root_errors) = list_files_in_dir(root, &self.path_filter, &self.walk_options);
+                files.extend(root_files);
+                listing.errors.extend(root_errors);
+            }
+
+            listing.files_found = files.len();
+            listing.time = start_listing.elapsed();
+
+            let (files, claims, read_errors) = self.claim_lines(&files, threads, &totals);
+            listing.errors.extend(read_errors);
+
+            search_files(options, threads, &claims, &totals, move |queue| {
+                for (path, contents) in files {
+                    queue.send(Job { path, contents: Some(contents) }).unwrap();
+                }
+
+                listing
+            })
+        } else {
+            // Files are searched as soon as they're found
+            search_files(options, threads, &Claims::default(), &totals, |queue| {
+                let mut listing = Listing::default();
+
+                for root in &options.roots {
+                    walk_files(root, &self.path_filter, &self.walk_options, |entry| match entry {
+                        Ok(path) => {
+                            listing.files_found += 1;
+                            queue.send(Job { path, contents: None }).unwrap();
+                        },
+                        Err(e) => listing.errors.push(e),
+                    });
+                }
+
+                listing.time = start_listing.elapsed();
+                listing
+            })
+        };
+        let mut errors = listing.errors;
+        errors.extend(search_errors);
+
+        // Sequences can only be compared once they've all been gathered
+        let results = match results {
+            SearchResult::Sequences(mut sequences) => {
+                sequences.sort_by(|a, b| a.path.cmp(&b.path));
+
+                let occurrences = if options.tokens {
+                    count_tokens(&sequences, options)
+                } else {
+                    count_blocks(&sequences, options)
+                };
+
+                SearchResult::AllFiles(occurrences)
+            },
+            results => results,
+        };
+
+        let (duplicates, changes) = match results {
+            SearchResult::RemoveDuplicates(mut changes) => {
+                changes.sort_by(|a, b| a.path.cmp(&b.path));
+
+                (vec![], changes)
+            },
+            SearchResult::Sequences(_) => unreachable!(),
+            SearchResult::SameFile(results) => {
+                let duplicates = results.into_iter()
+                    .flat_map(|one_file| one_file.into_iter().filter(|entry| entry.1.len() > 1));
+
+                (self.duplicates(duplicates)?, vec![])
+            },
+            SearchResult::AllFiles(results) => {
+                let duplicates = results.into_iter()
+                    .filter(|entry| entry.1.len() > 1);
+
+                (self.duplicates(duplicates)?, vec![])
+            },
+        };
+        let total_time = start_listing.elapsed();
+
+        errors.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(ScanReport {
+            unit:               duplicate_unit(options),
+            mode:               options.mode,
+            dry_run:            options.dry_run.is_some(),
+            duplicates,
+            changes,
+            errors,
+            files_searched:     listing.files_found,
+            binary_files:       totals.binary_files.into_inner(),
+            total_lines:        totals.lines_read.into_inner(),
+            timings:            Timings {
+                                    listing_ms:     listing.time.as_millis() as u64,
+                                    total_ms:       total_time.as_millis() as u64,
+                                },
+        })
+    }
+
+    /// Sort and fingerprint duplicate groups, and check them against the
+    /// baseline
+    fn duplicates(&self, groups: impl Iterator<Item=(String, Vec<FileLocation>)>) -> Result<Vec<Duplicate>, ScanError> {
+        // Locations are gathered in whatever order the files were searched in
+        let mut groups = groups
+            .map(|(text, mut locations)| {
+                locations.sort();
+                (text, locations)
+            })
+            .collect::<Vec<(String, Vec<FileLocation>)>>();
+        groups.sort();
+
+        let duplicates = groups.into_iter()
+            .map(|(text, locations)| Duplicate {
+                fingerprint: group_fingerprint(&self.options, &text, &locations),
+                text,
+                locations,
+            })
+            .collect::<Vec<Duplicate>>();
+
+        return apply_baseline(duplicates, &self.options);
+    }
+
+    /// Read every file and work out which file keeps each line in
+    /// cross-file remove mode. Each file is only read here, so that it's
+    /// deduped using the same contents its claims were worked out from.
+    /// Returns the text files with their contents, in priority order, and
+    /// the files that couldn't be read.
+    fn claim_lines(&self, files: &[PathBuf], threads: usize, totals: &Totals) -> (Vec<(PathBuf, DecodedText)>, Claims, Vec<FileError>) {
+        let options = &self.options;
+
+        let ordered_files = order_by_priority(files, options, &self.priority);
+        let read = map_items(&ordered_files, threads, |file_path| read_file(options, file_path, totals));
+
+        let mut text_files = Vec::new();
+        let mut errors = Vec::new();
+
+        for (file_path, result) in ordered_files.into_iter().zip(read) {
+            match result {
+                Ok(Some(decoded)) => text_files.push((file_path, decoded)),
+                Ok(None) => {},
+                Err(e) => errors.push(FileError::new(&file_path, e)),
+            }
+        }
+
+        let forms = map_items(&text_files, threads, |(file_path, decoded)| line_forms(file_path, &decoded.text, options));
+        let (claimed, preferred_forms) = claim_lines(&forms, options.keep);
+
+        let claims = Claims {
+            claimed_elsewhere: text_files.iter().map(|(file_path, _)| file_path.clone()).zip(claimed).collect(),
+            preferred_forms,
+        };
+
+        return (text_files, claims, errors);
+    }
+}
+
+/// Everything a scan found
+#[derive(Debug)]
+pub struct ScanReport {
+    pub unit: DuplicateUnit,
+    pub mode: Mode,
+    /// Whether `changes` only describe what would have been done
+    pub dry_run: bool,
+    /// Sorted by text. Empty in remove mode.
+    pub duplicates: Vec<Duplicate>,
+    /// Files changed (or that would be changed) in remove mode, sorted by path
+    pub changes: Vec<FileChange>,
+    /// Files and directories that couldn't be searched (or rewritten), sorted
+    /// by path
+    pub errors: Vec<FileError>,
+    pub files_searched: usize,
+    /// Files skipped because they look like binaries
+    pub binary_files: usize,
+    /// Lines in all of the files searched
+    pub total_lines: usize,
+    pub timings: Timings,
+}
+
+impl ScanReport {
+    pub fn summary(&self) -> Summary {
+        let summary = Summary {
+            files_searched:     self.files_searched,
+            files_with_errors:  self.errors.len(),
+            binary_files:       self.binary_files,
+            total_lines:        self.total_lines,
+            timings:            self.timings,
+            ..Summary::default()
+        };
+
+        match self.mode {
+            Mode::RemoveDuplicates | Mode::RemoveDuplicatesAcrossFiles => {
+                let removed_lines: usize = self.changes.iter().map(|change| change.removed_lines).sum();
+
+                Summary {
+                    files_with_duplicates:  self.changes.len(),
+                    removed_lines,
+                    duplicate_lines:        removed_lines,
+                    dry_run:                self.dry_run,
+                    ..summary
+                }
+            },
+            Mode::AllFiles | Mode::SameFile => {
+                let files_with_duplicates = self.duplicates.iter()
+                    .flat_map(|duplicate| duplicate.locations.iter().map(|location| &location.path))
+                    .collect::<HashSet<&PathBuf>>()
+                    .len();
+
+                Summary {
+                    files_with_duplicates,
+                    duplicate_groups:   self.duplicates.len(),
+                    duplicate_lines:    duplicate_line_count(&self.duplicates),
+                    ..summary
+                }
+            },
+        }
+    }
+}
+
+/// One duplicated line (or block, or run of tokens) and everywhere it occurs
+#[derive(Debug)]
+pub struct Duplicate {
+    /// Identifies the group across runs (see `baseline::fingerprint`)
+    pub fingerprint: String,
+    /// The normalized text the occurrences were compared by
+    pub text: String,
+    pub locations: Vec<FileLocation>,
+}
+
+/// The results of searching some files. Each thread gathers its own, and
+/// they're merged once every file has been searched.
+enum SearchResult {
+    AllFiles(Occurrences),
+    SameFile(Vec<Occurrences>),
+    /// Files that have to be compared with each other all at once (in token
+    /// and block modes), before they can be turned into `AllFiles` results
+    Sequences(Vec<Sequence>),
+    RemoveDuplicates(Vec<FileChange>),
+}
+
+impl SearchResult {
+    fn from_options(options: &Options) -> Self {
+        match options.mode {
+            Mode::AllFiles if options.tokens || options.blocks => Self::Sequences(Vec::new()),
+            Mode::AllFiles => Self::AllFiles(HashMap::new()),
+            Mode::SameFile => Self::SameFile(Vec::new()),
+            Mode::RemoveDuplicates | Mode::RemoveDuplicatesAcrossFiles => Self::RemoveDuplicates(Vec::new()),
+        }
+    }
+
+    /// Search (or dedupe) one more file
+    fn add_file(&mut self, options: &Options, job: Job, claims: &Claims, totals: &Totals) -> Result<(), FileErrorKind> {
+        let file_path = &job.path;
+        let contents = match job.contents {
+            Some(decoded) => decoded,
+            None => match read_file(options, file_path, totals)? {
+                Some(decoded) => decoded,
+                None => return Ok(()),
+            },
+        };
+
+        match self {
+            SearchResult::RemoveDuplicates(changes) => {
+                let claimed = claims.claimed_elsewhere.get(file_path).cloned().unwrap_or_default();
+
+                changes.extend(dedupe_file(options, file_path, &contents, &claimed, &claims.preferred_forms)?);
+            },
+            SearchResult::SameFile(results) => results.push(search_file(options, file_path, &contents.text)),
+            SearchResult::AllFiles(results) => merge_records(results, search_file(options, file_path, &contents.text)),
+            SearchResult::Sequences(sequences) => sequences.extend(sequence_file(options, file_path, &contents.text)),
+        }
+
+        return Ok(());
+    }
+
+    /// Add another thread's results to these
+    fn merge(&mut self, other: Self) {
+        match (self, other) {
+            (SearchResult::AllFiles(results), SearchResult::AllFiles(other)) => merge_records(results, other),
+            (SearchResult::SameFile(results), SearchResult::SameFile(other)) => results.extend(other),
+            (SearchResult::Sequences(sequences), SearchResult::Sequences(other)) => sequences.extend(other),
+            (SearchResult::RemoveDuplicates(changes), SearchResult::RemoveDuplicates(other)) => changes.extend(other),
+            _ => unreachable!("results from the same search are all the same kind"),
+        }
+    }
+}
+
+/// Running totals kept while files are searched
+#[derive(Default)]
+struct Totals {
+    lines_read: AtomicUsize,
+    binary_files: AtomicUsize,
+}
+
+/// What was found while listing files, apart from the files themselves
+#[derive(Default)]
+struct Listing {
+    files_found: usize,
+    errors: Vec<FileError>,
+    time: Duration,
+}
+
+/// A file to search, and its contents if they've already been read
+struct Job {
+    path: PathBuf,
+    contents: Option<DecodedText>,
+}
+
+/// In cross-file remove mode, the lines each file has to give up to another
+/// file, and which raw form of each line is kept
+#[derive(Default)]
+struct Claims {
+    claimed_elsewhere: HashMap<PathBuf, HashSet<String>>,
+    preferred_forms: HashMap<String, String>,
+}
+
+/// The number of threads to use when none is given
+fn default_threads() -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Search (or dedupe) every file that `feed` puts in the queue, using
+/// `threads` worker threads that each take one file at a time. `feed` runs
+/// on a thread of its own alongside the workers, and what it returns is
+/// passed back. Files that fail are returned separately and left out of the
+/// results.
+fn search_files<T: Send>(
+    options: &Options,
+    threads: usize,
+    claims: &Claims,
+    totals: &Totals,
+    feed: impl FnOnce(&Sender<Job>) -> T + Send,
+) -> (SearchResult, Vec<FileError>, T) {
+    let (sender, receiver) = channel::unbounded::<Job>();
+
+    crossbeam::scope(|scope| {
+        let feeder = scope.spawn(move |_| feed(&sender));
+
+        let workers = (0..threads)
+            .map(|_| {
+                let queue = receiver.clone();
+
+                scope.spawn(move |_| {
+                    let mut results = SearchResult::from_options(options);
+                    let mut errors = Vec::new();
+
+                    for job in queue.iter() {
+                        let file_path = job.path.clone();
+
+                        if let Err(e) = results.add_file(options, job, claims, totals) {
+                            errors.push(FileError::new(&file_path, e));
+                        }
+                    }
+
+                    (results, errors)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let fed = feeder.join().unwrap();
+
+        let mut results = SearchResult::from_options(options);
+        let mut errors = Vec::new();
+
+        for worker in workers {
+            let (worker_results, worker_errors) = worker.join().unwrap();
+            results.merge(worker_results);
+            errors.extend(worker_errors);
+        }
+
+        (results, errors, fed)
+    }).unwrap()
+}
+
+/// Run `f` on every item using `threads` threads, returning the results in
+/// the same order as `items`
+fn map_items<I: Sync, T: Send>(items: &[I], threads: usize, f: impl Fn(&I) -> T + Sync) -> Vec<T> {
+    let (sender, receiver) = channel::unbounded();
+    for item in items.iter().enumerate() {
+        sender.send(item).unwrap();
+    }
+    drop(sender);
+
+    let f = &f;
+    let mut results = items.iter().map(|_| None).collect::<Vec<Option<T>>>();
+
+    crossbeam::scope(|scope| {
+        let workers = (0..threads)
+            .map(|_| {
+                let queue = receiver.clone();
+
+                scope.spawn(move |_| queue.iter().map(|(index, item)| (index, f(item))).collect::<Vec<(usize, T)>>())
+            })
+            .collect::<Vec<_>>();
+
+        for worker in workers {
+            for (index, result) in worker.join().unwrap() {
+                results[index] = Some(result);
+            }
+        }
+    }).unwrap();
+
+    return results.into_iter().flatten().collect();
+}
+
+/// Write every group in `duplicates` to `options.write_baseline`, and drop
+/// the ones that are already in `options.baseline`
+fn apply_baseline(duplicates: Vec<Duplicate>, options: &Options) -> Result<Vec<Duplicate>, ScanError> {
+    if let Some(path) = &options.write_baseline {
+        let fingerprinted = duplicates.iter()
+            .map(|duplicate| (duplicate.fingerprint.clone(), &duplicate.locations))
+            .collect::<Vec<(String, &Vec<FileLocation>)>>();
+
+        Baseline::from_duplicates(&fingerprinted).write(path)
+            .map_err(|e| ScanError::WriteBaseline(path.clone(), e))?;
+    }
+
+    match &options.baseline {
+        Some(path) => {
+            let baseline = Baseline::read(path)
+                .map_err(|e| ScanError::ReadBaseline(path.clone(), e))?;
+
+            Ok(duplicates.into_iter()
+                .filter(|duplicate| baseline.is_new(&duplicate.fingerprint, duplicate.locations.len()))
+                .collect())
+        },
+        None => Ok(duplicates),
+    }
+}
+
+/// The fingerprint of a duplicate group. In same-file mode the same text can
+/// make up a separate group in each file, so the file is part of it.
+fn group_fingerprint(options: &Options, text: &str, locations: &[FileLocation]) -> String {
+    let file = match options.mode {
+        Mode::SameFile => locations.first()
+            .map(|location| options.relative_path(&location.path)),
+        _ => None,
+    };
+
+    return fingerprint(duplicate_unit(options), text, file);
+}
+
+/// Count the lines covered by every occurrence of each duplicate except the
+/// first. Lines covered by several occurrences are only counted once.
+fn duplicate_line_count(duplicates: &[Duplicate]) -> usize {
+    let mut lines = HashSet::new();
+
+    for duplicate in duplicates {
+        let first = duplicate.locations.iter().min().unwrap();
+
+        for location in duplicate.locations.iter().filter(|location| !std::ptr::eq(*location, first)) {
+            lines.extend((location.line_number..=location.end_line_number).map(|n| (&location.path, n)));
+        }
+    }
+
+    return lines.len();
+}
+
+/// What a duplicate is made of
+fn duplicate_unit(options: &Options) -> DuplicateUnit {
+    if options.tokens {
+        DuplicateUnit::Tokens
+    } else if options.blocks {
+        DuplicateUnit::Blocks
+    } else {
+        DuplicateUnit::Lines
+    }
+}
+
+fn search_file(options: &Options, file_path: &Path, contents: &str) -> Occurrences {
+    if options.tokens {
+        count_token_runs(
+            file_path,
+            contents,
+            options,
+        )
+    } else if options.blocks {
+        count_chunks(
+            file_path,
+            contents,
+            options,
+        )
+    } else {
+        count_lines(
+            file_path,
+            contents,
+            options,
+        )
+    }
+}
+
+/// Read and decode a file, adding its number of lines to `totals`. Binary
+/// files are counted in `totals` as well, and `None` is returned for them.
+fn read_file(options: &Options, file_path: &Path, totals: &Totals) -> Result<Option<DecodedText>, FileErrorKind> {
+    let decoded = match decode(&fs::read(file_path)?, options.encoding)? {
+        Some(decoded) => decoded,
+        None => {
+            totals.binary_files.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
+        },
+    };
+    let contents = &decoded.text;
+
+    let delimiters = contents.matches(options.line_delimiter).count();
+    let unterminated = !contents.is_empty() && !contents.ends_with(options.line_delimiter);
+    totals.lines_read.fetch_add(delimiters + unterminated as usize, Ordering::Relaxed);
+
+    return Ok(Some(decoded));
+}
+
+fn sequence_file(options: &Options, file_path: &Path, contents: &str) -> Vec<Sequence> {
+    if options.tokens {
+        return tokenize_file(file_path, contents, options);
+    } else {
+        return block_sequences(file_path, contents, options);
+    }
+}
+
+/// Remove duplicate lines from a file, or in a dry run just work out what
+/// would be removed. Lines in `claimed_elsewhere` (kept by another file) are
+/// removed as well. The file is written back in the encoding it was read
+/// in. Returns `None` if the file has no duplicates.
+fn dedupe_file(
+    options: &Options,
+    file_path: &Path,
+    decoded: &DecodedText,
+    claimed_elsewhere: &HashSet<String>,
+    preferred_forms: &HashMap<String, String>,
+) -> Result<Option<FileChange>, FileErrorKind> {
+    let contents = &decoded.text;
+
+    let (new_contents, removed_lines) = strip_lines(
+        file_path,
+        contents,
+        options,
+        claimed_elsewhere,
+        preferred_forms,
+    );
+
+    if removed_lines == 0 {
+        return Ok(None);
+    }
+
+    let diff = match options.dry_run {
+        Some(DryRun::Diff) => {
+            let path = file_path.to_string_lossy();
+
+            let diff = TextDiff::from_lines(contents, &new_contents)
+                .unified_diff()
+                .header(&path, &path)
+                .to_string();
+
+            Some(escape_raw_bytes(&diff).into_owned())
+        },
+        Some(DryRun::Count) => None,
+        None => {
+            write_atomically(file_path, &decoded.encode(&new_contents), options.backup_suffix.as_deref())?;
+
+            None
+        },
+    };
+
+    Ok(Some(FileChange {
+        path: file_path.to_path_buf(),
+        removed_lines,
+        diff,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_files(root: &Path, files: &[(&str, &[u8])]) {
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+    }
+
+    fn locations(duplicate: &Duplicate, root: &Path) -> Vec<(PathBuf, usize)> {
+        duplicate.locations.iter()
+            .map(|location| (location.path.strip_prefix(root).unwrap().to_path_buf(), location.line_number))
+            .collect()
+    }
+
+    #[test]
+    fn scan_finds_lines_duplicated_across_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &[
+            ("a.txt", b"x\ny\n"),
+            ("sub/b.txt", b"z\nx\n"),
+            ("data.bin", b"x\0x\n"),
+        ]);
+
+        let report = Scanner::new().root(dir.path()).scan().unwrap();
+
+        assert_eq!(report.unit, DuplicateUnit::Lines);
+        assert_eq!(report.duplicates.len(), 1);
+        assert_eq!(report.duplicates[0].text, "x");
+        assert_eq!(locations(&report.duplicates[0], dir.path()), [(PathBuf::from("a.txt"), 1), (PathBuf::from("sub/b.txt"), 2)]);
+        assert_eq!(report.binary_files, 1);
+        assert!(report.errors.is_empty());
+    }
+
+    #[test]
+    fn scan_in_same_file_mode_keeps_files_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &[
+            ("a.txt", b"x\nx\ny\n"),
+            ("b.txt", b"y\n"),
+        ]);
+
+        let report = Scanner::new().root(dir.path()).mode(Mode::SameFile).scan().unwrap();
+
+        assert_eq!(report.duplicates.len(), 1);
+        assert_eq!(locations(&report.duplicates[0], dir.path()), [(PathBuf::from("a.txt"), 1), (PathBuf::from("a.txt"), 2)]);
+    }
+
+    #[test]
+    fn scan_in_remove_mode_rewrites_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &[
+            ("a.txt", b"x\nx\ny\n"),
+            ("b.txt", b"y\nz\n"),
+        ]);
+
+        let report = Scanner::new().root(dir.path()).mode(Mode::RemoveDuplicates).scan().unwrap();
+
+        assert!(report.duplicates.is_empty());
+        assert_eq!(report.changes.len(), 1);
+        assert_eq!(report.changes[0].removed_lines, 1);
+        assert_eq!(fs::read(dir.path().join("a.txt")).unwrap(), b"x\ny\n");
+        assert_eq!(fs::read(dir.path().join("b.txt")).unwrap(), b"y\nz\n");
+    }
+
+    #[test]
+    fn scan_reports_what_duplicates_are_made_of() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &[("a.rs", b"fn f() { g(1); }\nfn h() { g(1); }\n")]);
+
+        let report = Scanner::new().root(dir.path()).tokens(5).scan().unwrap();
+
+        assert_eq!(report.unit, DuplicateUnit::Tokens);
+        assert_eq!(report.duplicates.len(), 1);
+        assert_eq!(report.duplicates[0].text, "( ) { g ( 1 ) ; }");
+    }
+
+    #[test]
+    fn scan_fails_for_a_missing_root() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(Scanner::new().root(dir.path().join("missing")).scan().is_err());
+    }
+}
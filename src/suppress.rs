@@ -0,0 +1,188 @@
+use std::path::Path;
+
+use crate::comments::comment_text;
+
+/// Suppresses the line it's on
+pub const IGNORE_LINE: &str = "strainer-ignore-line";
+/// Suppresses the line it's on and the line after it
+pub const IGNORE_NEXT_LINE: &str = "strainer-ignore-next-line";
+/// Suppresses every line from here up to and including the matching
+/// `IGNORE_END`. Pairs of these can be nested.
+pub const IGNORE_START: &str = "strainer-ignore-start";
+pub const IGNORE_END: &str = "strainer-ignore-end";
+/// Suppresses the whole file, if it appears within its first
+/// `IGNORE_FILE_LINES` lines
+pub const IGNORE_FILE: &str = "strainer-ignore-file";
+
+pub const IGNORE_FILE_LINES: usize = 10;
+
+const MARKER_PREFIX: &str = "strainer-ignore";
+
+/// Tracks suppression markers through the lines of a file, one line at a
+/// time. Lines with markers on them are always suppressed themselves, so that
+/// the markers don't get reported as duplicates of each other.
+#[derive(Debug, Default)]
+pub struct Suppressor {
+    lines_seen: usize,
+    next_line: bool,
+    /// How many `IGNORE_START` markers haven't been ended yet
+    block_depth: usize,
+    rest_of_file: bool,
+}
+
+impl Suppressor {
+    pub fn new() -> Self {
+        Suppressor::default()
+    }
+
+    /// Whether the next line of the file is suppressed. `marker_text` is
+    /// where markers are looked for on that line: its comments, or the
+    /// whole line if they aren't known.
+    pub fn is_suppressed(&mut self, marker_text: &str) -> bool {
+        self.lines_seen += 1;
+
+        let after_marker = std::mem::take(&mut self.next_line);
+        let suppressed = self.rest_of_file || self.block_depth > 0 || after_marker;
+
+        // Every marker starts the same way, so most lines can be ruled out
+        // with a single search
+        if !marker_text.contains(MARKER_PREFIX) {
+            return suppressed;
+        }
+
+        if self.lines_seen <= IGNORE_FILE_LINES && marker_text.contains(IGNORE_FILE) {
+            self.rest_of_file = true;
+        }
+
+        if marker_text.contains(IGNORE_NEXT_LINE) {
+            self.next_line = true;
+        }
+
+        self.block_depth += marker_text.matches(IGNORE_START).count();
+        self.block_depth = self.block_depth.saturating_sub(marker_text.matches(IGNORE_END).count());
+
+        let marked = [IGNORE_LINE, IGNORE_NEXT_LINE, IGNORE_START, IGNORE_END, IGNORE_FILE].iter()
+            .any(|marker| marker_text.contains(marker));
+
+        return suppressed || marked;
+    }
+}
+
+/// For each line of `text`, split on `delimiter`, whether it's suppressed. If
+/// the language of `file_path` is recognized, markers only count inside
+/// comments, so a string that happens to contain one doesn't suppress
+/// anything. A file with an `IGNORE_FILE` marker near the top has every line
+/// suppressed.
+pub fn suppressed_lines(file_path: &Path, text: &str, delimiter: char) -> Vec<bool> {
+    let line_count = text.split(delimiter).count();
+
+    if !text.contains(MARKER_PREFIX) {
+        return vec![false; line_count];
+    }
+
+    let marker_text = comment_text(file_path, text, delimiter);
+    let mut suppressor = Suppressor::new();
+
+    let suppressed = marker_text.as_deref().unwrap_or(text)
+        .split(delimiter)
+        .map(|line| suppressor.is_suppressed(line))
+        .collect::<Vec<bool>>();
+
+    if suppressor.rest_of_file {
+        return vec![true; line_count];
+    }
+
+    return suppressed;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn suppressed(file_name: &str, lines: &[&str]) -> Vec<bool> {
+        suppressed_lines(Path::new(file_name), &lines.join("\n"), '\n')
+    }
+
+    #[test]
+    fn ignore_line_suppresses_only_its_own_line() {
+        let lines = ["a", "b // strainer-ignore-line", "c"];
+
+        assert_eq!(suppressed("file.rs", &lines), vec![false, true, false]);
+    }
+
+    #[test]
+    fn ignore_next_line_suppresses_the_line_after_it() {
+        let lines = ["a", "// strainer-ignore-next-line", "b", "c"];
+
+        assert_eq!(suppressed("file.rs", &lines), vec![false, true, true, false]);
+    }
+
+    #[test]
+    fn start_and_end_suppress_everything_between_them() {
+        let lines = ["a", "// strainer-ignore-start", "b", "// strainer-ignore-end", "c"];
+
+        assert_eq!(suppressed("file.rs", &lines), vec![false, true, true, true, false]);
+    }
+
+    #[test]
+    fn start_and_end_can_be_nested() {
+        let lines = [
+            "// strainer-ignore-start",
+            "// strainer-ignore-start",
+            "a",
+            "// strainer-ignore-end",
+            "b",
+            "// strainer-ignore-end",
+            "c",
+        ];
+
+        assert_eq!(suppressed("file.rs", &lines), vec![true, true, true, true, true, true, false]);
+    }
+
+    #[test]
+    fn unmatched_end_is_ignored() {
+        let lines = ["// strainer-ignore-end", "a", "// strainer-ignore-start", "b"];
+
+        assert_eq!(suppressed("file.rs", &lines), vec![true, false, true, true]);
+    }
+
+    #[test]
+    fn ignore_file_near_the_top_suppresses_the_whole_file() {
+        let mut lines = vec!["a"; IGNORE_FILE_LINES + 5];
+        lines[IGNORE_FILE_LINES - 1] = "// strainer-ignore-file";
+
+        assert_eq!(suppressed("file.rs", &lines), vec![true; lines.len()]);
+    }
+
+    #[test]
+    fn ignore_file_further_down_only_suppresses_its_own_line() {
+        let mut lines = vec!["a"; IGNORE_FILE_LINES + 5];
+        lines[IGNORE_FILE_LINES] = "// strainer-ignore-file";
+
+        let mut expected = vec![false; lines.len()];
+        expected[IGNORE_FILE_LINES] = true;
+
+        assert_eq!(suppressed("file.rs", &lines), expected);
+    }
+
+    #[test]
+    fn markers_in_strings_are_ignored_in_known_languages() {
+        let lines = ["let a = \"strainer-ignore-start\";", "b", "c"];
+
+        assert_eq!(suppressed("file.rs", &lines), vec![false, false, false]);
+    }
+
+    #[test]
+    fn markers_in_block_comments_count() {
+        let lines = ["a /* strainer-ignore-next-line */", "b", "c"];
+
+        assert_eq!(suppressed("file.rs", &lines), vec![true, true, false]);
+    }
+
+    #[test]
+    fn markers_count_anywhere_in_unknown_languages() {
+        let lines = ["\"strainer-ignore-start\"", "b", "c"];
+
+        assert_eq!(suppressed("file.unknown", &lines), vec![true, true, true]);
+    }
+}
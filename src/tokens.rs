@@ -0,0 +1,230 @@
+use std::path::Path;
+
+use crate::{comments::{CommentSyntax, PieceKind, Pieces, syntax_for_path}, counting::Occurrences, options::Options, repeats::{Sequence, Unit, count_repeats}, suppress::suppressed_lines};
+
+/// Stands in for every identifier when `options.parameterize` is set
+pub const IDENTIFIER_PLACEHOLDER: &str = "$id";
+
+/// Stands in for every number, string and character literal when
+/// `options.parameterize` is set
+pub const LITERAL_PLACEHOLDER: &str = "$lit";
+
+/// Words that aren't abstracted away as identifiers. Abstracting these
+/// would make any two pieces of code with the same punctuation look alike,
+/// so this is the union of the keywords (and built-in names) of all the
+/// languages we recognize.
+const KEYWORDS: &[&str] = &[
+    "abstract", "and", "as", "async", "await", "begin", "bool", "boolean", "break", "byte", "case",
+    "catch", "chan", "char", "class", "const", "continue", "def", "default", "defer", "del", "delete",
+    "do", "double", "dyn", "elif", "else", "end", "enum", "except", "export", "extends", "extern",
+    "false", "False", "final", "finally", "float", "fn", "for", "from", "func", "function", "go",
+    "goto", "if", "impl", "implements", "import", "in", "instanceof", "int", "interface", "internal",
+    "is", "lambda", "let", "local", "long", "loop", "map", "match", "mod", "module", "mut", "namespace",
+    "new", "nil", "None", "not", "null", "of", "or", "override", "package", "pass", "private",
+    "protected", "pub", "public", "raise", "range", "ref", "require", "return", "select", "self",
+    "Self", "short", "signed", "sizeof", "static", "string", "struct", "super", "switch", "then",
+    "this", "throw", "throws", "trait", "true", "True", "try", "type", "typedef", "typeof", "undefined",
+    "unless", "unsigned", "until", "use", "val", "var", "virtual", "void", "where", "while", "with",
+    "yield",
+];
+
+/// Split a file into tokens, if its language is recognized. Comments and
+/// whitespace are dropped, so reformatted copies of the same code produce the
+/// same tokens. If `options.parameterize` is set, identifiers and literals are
+/// replaced with placeholders (keeping their original text as `raw`).
+///
+/// Tokens on suppressed lines (see `suppress`) are left out, and split the
+/// file into separate sequences so that no run can span them.
+pub fn tokenize_file(file_path: &Path, text: &str, options: &Options) -> Vec<Sequence> {
+    let syntax = match syntax_for_path(file_path) {
+        Some(syntax) => syntax,
+        None => return vec![],
+    };
+
+    let suppressed = suppressed_lines(file_path, text, '\n');
+    let mut sequences = Vec::new();
+    let mut units = Vec::new();
+
+    for unit in tokenize(text, syntax, options.parameterize) {
+        if !suppressed[unit.line_number - 1] {
+            units.push(unit);
+        } else if !units.is_empty() {
+            sequences.push(Sequence {
+                path: file_path.to_path_buf(),
+                units: std::mem::take(&mut units),
+            });
+        }
+    }
+
+    if !units.is_empty() {
+        sequences.push(Sequence {
+            path: file_path.to_path_buf(),
+            units,
+        });
+    }
+
+    return sequences;
+}
+
+/// Return a record of every duplicated run of tokens within a single file
+pub fn count_token_runs(
+    file_path: &Path,
+    text: &str,
+    options: &Options,
+) -> Occurrences {
+    count_tokens(&tokenize_file(file_path, text, options), options)
+}
+
+/// Return a record of every duplicated run of tokens across all of
+/// `sequences`
+pub fn count_tokens(sequences: &[Sequence], options: &Options) -> Occurrences {
+    count_repeats(sequences, options.min_tokens..=usize::MAX, " ", options.consistent_renaming)
+}
+
+/// A simple lexer that works well enough for most C-family and scripting
+/// languages: identifiers, numbers and string literals each make up one
+/// token, and every other non-whitespace character is a token on its own.
+/// Comments and strings are found by `Pieces`.
+fn tokenize(text: &str, syntax: &CommentSyntax, parameterize: bool) -> Vec<Unit> {
+    let mut tokens = Vec::new();
+    let mut pieces = Pieces::new(text, syntax, '\n');
+
+    // The position of `counted_up_to` in the text
+    let mut line_number = 1;
+    let mut column = 1;
+    let mut counted_up_to = 0;
+
+    while let Some(piece) = pieces.next() {
+        let rest = &text[piece.start..];
+        let c = rest.chars().next().unwrap();
+
+        let (len, placeholder) = match piece.kind {
+            PieceKind::Str => {
+                let mut end = piece.end;
+
+                // An unclosed string that can't span lines ends before the
+                // line break
+                while pieces.in_string() {
+                    match pieces.next() {
+                        Some(next) if next.kind == PieceKind::Str || pieces.in_string() => end = next.end,
+                        _ => break,
+                    }
+                }
+
+                (end - piece.start, Some(LITERAL_PLACEHOLDER))
+            },
+            PieceKind::CharLiteral => (piece.end - piece.start, Some(LITERAL_PLACEHOLDER)),
+            PieceKind::Code if c.is_whitespace() => continue,
+            PieceKind::Code if c.is_numeric() => (number_len(rest), Some(LITERAL_PLACEHOLDER)),
+            PieceKind::Code if c.is_alphanumeric() || c == '_' => {
+                let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                let is_keyword = KEYWORDS.contains(&&rest[..len]);
+
+                (len, if is_keyword { None } else { Some(IDENTIFIER_PLACEHOLDER) })
+            },
+            PieceKind::Code => (c.len_utf8(), None),
+            PieceKind::CommentMarker | PieceKind::Comment | PieceKind::LineBreak => continue,
+        };
+
+        pieces.skip_to(piece.start + len);
+
+        for c in text[counted_up_to..piece.start].chars() {
+            if c == '\n' {
+                line_number += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        counted_up_to = piece.start;
+
+        let token = &rest[..len];
+
+        tokens.push(match placeholder {
+            Some(placeholder) if parameterize => Unit {
+                text: placeholder.to_owned(),
+                raw: Some(token.to_owned()),
+                line_number,
+                column,
+            },
+            _ => Unit {
+                text: token.to_owned(),
+                raw: None,
+                line_number,
+                column,
+            },
+        });
+    }
+
+    return tokens;
+}
+
+/// Length of the number at the start of `text`, including any suffix (as in
+/// `1u8` or `0x1F`). It can have one decimal point, but only if a digit
+/// follows it, so `1..10` and `a[0].b` keep their dots as separate tokens.
+fn number_len(text: &str) -> usize {
+    let digits_len = |text: &str| text.find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(text.len());
+
+    let len = digits_len(text);
+    let fraction = &text[len..];
+
+    if fraction.starts_with('.') && fraction[1..].starts_with(|c: char| c.is_ascii_digit()) {
+        return len + 1 + digits_len(&fraction[1..]);
+    }
+
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comments::syntax_for_path;
+
+    fn token_texts(text: &str, extension: &str) -> Vec<String> {
+        let syntax = syntax_for_path(Path::new(&format!("file.{}", extension))).unwrap();
+
+        tokenize(text, syntax, false).into_iter().map(|unit| unit.text).collect()
+    }
+
+    #[test]
+    fn comments_are_dropped() {
+        assert_eq!(token_texts("a = 1; // one\n/* two */ b", "js"), ["a", "=", "1", ";", "b"]);
+    }
+
+    #[test]
+    fn numbers_have_at_most_one_decimal_point() {
+        assert_eq!(token_texts("x = 1.5e3 + 0x1F;", "rs"), ["x", "=", "1.5e3", "+", "0x1F", ";"]);
+        assert_eq!(token_texts("for i in 1..10", "rs"), ["for", "i", "in", "1", ".", ".", "10"]);
+        assert_eq!(token_texts("a[0].b", "js"), ["a", "[", "0", "]", ".", "b"]);
+    }
+
+    #[test]
+    fn tokens_know_their_position() {
+        let syntax = syntax_for_path(Path::new("file.rs")).unwrap();
+        let positions = tokenize("let é = 1;\n  /* a\nb */ x", syntax, false).into_iter()
+            .map(|unit| (unit.text, unit.line_number, unit.column))
+            .collect::<Vec<_>>();
+
+        assert_eq!(positions, [
+            ("let".to_owned(), 1, 1),
+            ("é".to_owned(), 1, 5),
+            ("=".to_owned(), 1, 7),
+            ("1".to_owned(), 1, 9),
+            (";".to_owned(), 1, 10),
+            ("x".to_owned(), 3, 6),
+        ]);
+    }
+
+    #[test]
+    fn lua_block_comments_are_dropped() {
+        assert_eq!(token_texts("a\n--[[ x = 1\ny = 2 ]]\nb -- c", "lua"), ["a", "b"]);
+    }
+
+    #[test]
+    fn unclosed_strings_end_with_their_line() {
+        assert_eq!(token_texts("title: It's here\nkey: value", "yml"), ["title", ":", "It", "'s here", "key", ":", "value"]);
+        assert_eq!(token_texts("s = `a\nb`", "js"), ["s", "=", "`a\nb`"]);
+    }
+}